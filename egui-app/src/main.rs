@@ -1,13 +1,7 @@
 use eframe::egui;
 use tray_icon::{TrayIconBuilder, Icon};
 use tray_icon::menu::{Menu, MenuItem};
-use netstat2::{get_sockets_info, AddressFamilyFlags, ProtocolFlags, SocketInfo};
-
-fn get_ports() -> Vec<SocketInfo> {
-    let af_flags = AddressFamilyFlags::IPV4 | AddressFamilyFlags::IPV6;
-    let proto_flags = ProtocolFlags::TCP | ProtocolFlags::UDP;
-    get_sockets_info(af_flags, proto_flags).unwrap_or_default()
-}
+use port_manager_core::list_ports;
 
 pub struct PortManagerApp {
     filter: String,
@@ -30,13 +24,26 @@ impl eframe::App for PortManagerApp {
                 ui.text_edit_singleline(&mut self.filter);
             });
             ui.separator();
-            let ports = get_ports();
+            let ports = list_ports();
             egui::ScrollArea::vertical().show(ui, |ui| {
-                for socket in ports.iter().filter(|s| {
-                    self.filter.is_empty() || format!("{:?}", s).contains(&self.filter)
-                }) {
-                    ui.label(format!("{:?}", socket));
-                }
+                egui::Grid::new("ports").striped(true).show(ui, |ui| {
+                    ui.strong("Protocol");
+                    ui.strong("Local Address");
+                    ui.strong("Remote Address");
+                    ui.strong("State");
+                    ui.strong("PID");
+                    ui.strong("Process");
+                    ui.end_row();
+                    for p in ports.iter().filter(|p| p.matches(&self.filter)) {
+                        ui.label(p.protocol.as_str());
+                        ui.label(&p.local_addr);
+                        ui.label(&p.remote_addr);
+                        ui.label(&p.state);
+                        ui.label(p.pid.map_or("-".into(), |pid| pid.to_string()));
+                        ui.label(p.process.as_deref().unwrap_or("-"));
+                        ui.end_row();
+                    }
+                });
             });
         });
     }
@@ -0,0 +1,49 @@
+use crate::auth::AuthUser;
+use crate::error::ApiError;
+use crate::roles::{Forbidden, Permission};
+use crate::AppState;
+use actix_web::{web, HttpResponse};
+use port_manager_core::{AuditEntry, AuditQuery, PortReservationManager};
+
+/// Append `entry` to the audit log. A store that can't take it is logged rather than
+/// failing the request, which has already happened.
+pub fn record(manager: &PortReservationManager, entry: AuditEntry) {
+    if let Err(e) = manager.record_audit(&entry) {
+        log::warn!("failed to audit {} by {}: {}", entry.action, entry.actor, e);
+    }
+}
+
+/// Record `entry` as having ended like `result`, keeping the error code of a failure
+pub fn record_result<T>(manager: &PortReservationManager, entry: AuditEntry, result: &Result<T, actix_web::Error>) {
+    let entry = match result {
+        Ok(_) => entry,
+        Err(e) => match (e.as_error::<ApiError>(), e.as_error::<Forbidden>()) {
+            (Some(e), _) => entry.failed(e.code, e.message.clone()),
+            (_, Some(e)) => entry.failed(e.code, e.message.clone()),
+            _ => entry.failed("ERROR", e.to_string()),
+        },
+    };
+    record(manager, entry);
+}
+
+/// Read the audit log
+///
+/// Entries come newest first, at most `limit` (100 by default) of them.
+#[utoipa::path(
+    get, path = "/audit", tag = "audit",
+    params(AuditQuery),
+    responses(
+        (status = 200, body = Vec<AuditEntry>),
+        (status = 401, body = ApiError),
+        (status = 403, body = Forbidden),
+    ),
+)]
+pub async fn list_audit(
+    data: web::Data<AppState>,
+    user: AuthUser,
+    query: web::Query<AuditQuery>,
+) -> Result<HttpResponse, actix_web::Error> {
+    user.require(Permission::ViewAudit)?;
+    let entries = data.manager.audit_log(&query).map_err(ApiError::from)?;
+    Ok(HttpResponse::Ok().json(entries))
+}
@@ -0,0 +1,259 @@
+use std::collections::HashSet;
+use std::future::{ready, Ready};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Duration;
+use actix_web::{dev::Payload, http::StatusCode, web, FromRequest, HttpRequest};
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
+use port_manager_core::reservation::migrate;
+use port_manager_core::{unix_now, Reservation};
+use rand::rngs::OsRng;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use crate::error::ApiError;
+use crate::peer::PeerCred;
+use crate::roles::{Forbidden, Permission, Roles};
+
+/// Role given to users created without one
+pub const DEFAULT_ROLE: &str = "developer";
+/// How long a token from `/login` stays valid
+pub const TOKEN_LIFETIME: Duration = Duration::from_secs(60 * 60);
+
+const MIGRATIONS: &[&str] = &[
+    "CREATE TABLE IF NOT EXISTS users (
+        username TEXT PRIMARY KEY,
+        password TEXT NOT NULL
+    );",
+    "ALTER TABLE users ADD COLUMN role TEXT NOT NULL DEFAULT 'developer';",
+];
+
+#[derive(Serialize, Deserialize, utoipa::ToSchema)]
+pub struct User {
+    pub username: String,
+    pub password: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Claims {
+    pub sub: String,
+    pub role: String,
+    pub exp: usize,
+}
+
+/// User database, the key that signs and checks tokens, and what each role may do
+pub struct Auth {
+    users: Mutex<Connection>,
+    encoding: EncodingKey,
+    decoding: DecodingKey,
+    roles: Roles,
+}
+
+impl Auth {
+    pub fn open(path: impl AsRef<Path>, secret: &[u8], roles: Roles) -> Result<Self, String> {
+        let path = path.as_ref();
+        if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+        }
+        let mut conn = Connection::open(path).map_err(|e| e.to_string())?;
+        conn.busy_timeout(Duration::from_secs(5)).map_err(|e| e.to_string())?;
+        migrate(&mut conn, MIGRATIONS).map_err(|e| e.to_string())?;
+        Ok(Self {
+            users: Mutex::new(conn),
+            encoding: EncodingKey::from_secret(secret),
+            decoding: DecodingKey::from_secret(secret),
+            roles,
+        })
+    }
+
+    /// Create a user, or reset the password and role of an existing one
+    pub fn add_user(&self, username: &str, password: &str, role: &str) -> Result<(), String> {
+        if !self.roles.contains(role) {
+            return Err(format!("Unknown role '{}'.", role));
+        }
+        let hash = hash_password(password)?;
+        let conn = self.users.lock().unwrap();
+        conn.execute(
+            "INSERT INTO users (username, password, role) VALUES (?1, ?2, ?3)
+             ON CONFLICT (username) DO UPDATE SET password = excluded.password, role = excluded.role",
+            params![username, hash, role],
+        ).map_err(|e| e.to_string())?;
+        Ok(())
+    }
+
+    /// Check credentials and issue a signed token carrying the user's role
+    pub fn login(&self, user: &User) -> Result<String, String> {
+        let denied = || "Invalid username or password.".to_string();
+        let conn = self.users.lock().unwrap();
+        let (stored, role): (String, String) = conn
+            .query_row("SELECT password, role FROM users WHERE username = ?1", [&user.username], |r| {
+                Ok((r.get(0)?, r.get(1)?))
+            })
+            .optional()
+            .map_err(|e| e.to_string())?
+            .ok_or_else(denied)?;
+        // Anything but an argon2 hash (a hand-edited row, say) never matches
+        let hash = PasswordHash::new(&stored).map_err(|_| denied())?;
+        Argon2::default().verify_password(user.password.as_bytes(), &hash).map_err(|_| denied())?;
+        let claims = Claims {
+            sub: user.username.clone(),
+            role,
+            exp: (unix_now() + TOKEN_LIFETIME.as_secs()) as usize,
+        };
+        encode(&Header::default(), &claims, &self.encoding).map_err(|e| e.to_string())
+    }
+
+    /// Decode a token, rejecting bad signatures and expired tokens
+    pub fn verify(&self, token: &str) -> Result<Claims, String> {
+        decode::<Claims>(token, &self.decoding, &Validation::default())
+            .map(|data| data.claims)
+            .map_err(|e| e.to_string())
+    }
+}
+
+fn hash_password(password: &str) -> Result<String, String> {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| e.to_string())
+}
+
+/// The caller, taken from a valid `Authorization: Bearer` token, or from the peer
+/// credentials of a Unix socket connection when there is no token. Handlers that take
+/// this as an argument reject anonymous requests with 401.
+pub struct AuthUser {
+    pub username: String,
+    pub role: String,
+    pub permissions: HashSet<Permission>,
+}
+
+impl AuthUser {
+    pub fn can(&self, permission: Permission) -> bool {
+        self.permissions.contains(&permission)
+    }
+
+    /// Refuse the call unless the caller's role grants `permission`
+    pub fn require(&self, permission: Permission) -> Result<(), Forbidden> {
+        if self.can(permission) {
+            Ok(())
+        } else {
+            Err(Forbidden::new(
+                &self.role,
+                Some(permission),
+                format!("Role '{}' may not do this.", self.role),
+            ))
+        }
+    }
+
+    /// Only the holder of a reservation, or a role that may release anyone's, may
+    /// release or renew it
+    pub fn may_manage(&self, reservation: &Reservation) -> Result<(), Forbidden> {
+        if self.can(Permission::ReleaseAny) || reservation.owner.as_deref() == Some(self.username.as_str()) {
+            Ok(())
+        } else {
+            Err(Forbidden::new(
+                &self.role,
+                Some(Permission::ReleaseAny),
+                format!(
+                    "Port {} is reserved by {}.",
+                    reservation.port,
+                    reservation.owner.as_deref().unwrap_or("another user")
+                ),
+            ))
+        }
+    }
+}
+
+impl FromRequest for AuthUser {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let auth = match req.app_data::<web::Data<Auth>>() {
+            Some(auth) => auth,
+            None => {
+                let e = ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "INTERNAL", "Authentication is not configured.");
+                return ready(Err(e.into()));
+            }
+        };
+        let token = req
+            .headers()
+            .get(actix_web::http::header::AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "));
+        let result = match token {
+            Some(token) => auth
+                .verify(token)
+                .map(|claims| AuthUser {
+                    permissions: auth.roles.permissions(&claims.role),
+                    username: claims.sub,
+                    role: claims.role,
+                })
+                .map_err(|e| ApiError::unauthorized(format!("Invalid token: {}", e)).into()),
+            None => match req.conn_data::<PeerCred>() {
+                Some(peer) => {
+                    let role = peer.role(DEFAULT_ROLE).to_string();
+                    Ok(AuthUser { permissions: auth.roles.permissions(&role), username: peer.username(), role })
+                }
+                None => Err(ApiError::unauthorized("Missing bearer token.").into()),
+            },
+        };
+        ready(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn auth(dir: &tempfile::TempDir, secret: &[u8]) -> Auth {
+        Auth::open(dir.path().join("users.db"), secret, Roles::default()).unwrap()
+    }
+
+    fn user(username: &str, password: &str) -> User {
+        User { username: username.to_string(), password: password.to_string() }
+    }
+
+    #[test]
+    fn logs_in_with_the_right_password_only() {
+        let dir = tempfile::tempdir().unwrap();
+        let auth = auth(&dir, b"secret");
+        auth.add_user("alice", "hunter2", "admin").unwrap();
+        let claims = auth.verify(&auth.login(&user("alice", "hunter2")).unwrap()).unwrap();
+        assert_eq!((claims.sub.as_str(), claims.role.as_str()), ("alice", "admin"));
+        assert!(claims.exp as u64 > unix_now());
+
+        assert_eq!(auth.login(&user("alice", "hunter3")), Err("Invalid username or password.".to_string()));
+        assert_eq!(auth.login(&user("bob", "hunter2")), Err("Invalid username or password.".to_string()));
+        assert!(auth.add_user("carol", "pw", "superuser").is_err());
+    }
+
+    #[test]
+    fn never_accepts_a_stored_plain_text_password() {
+        let dir = tempfile::tempdir().unwrap();
+        let auth = auth(&dir, b"secret");
+        auth.users.lock().unwrap().execute("INSERT INTO users (username, password) VALUES ('old', 'pw')", []).unwrap();
+        assert!(auth.login(&user("old", "pw")).is_err());
+    }
+
+    #[test]
+    fn rejects_forged_and_expired_tokens() {
+        let dir = tempfile::tempdir().unwrap();
+        let auth = auth(&dir, b"secret");
+        let claims = |exp: u64| Claims { sub: "alice".to_string(), role: "admin".to_string(), exp: exp as usize };
+        let sign = |claims: &Claims, secret: &[u8]| {
+            encode(&Header::default(), claims, &EncodingKey::from_secret(secret)).unwrap()
+        };
+        let valid = claims(unix_now() + 60);
+        assert!(auth.verify(&sign(&valid, b"secret")).is_ok());
+        assert!(auth.verify(&sign(&valid, b"another secret")).is_err());
+        // Past the 60 seconds of leeway `Validation` allows for clock skew
+        assert!(auth.verify(&sign(&claims(unix_now() - 120), b"secret")).is_err());
+        assert!(auth.verify("not.a.token").is_err());
+        let token = sign(&valid, b"secret");
+        let (head, _) = token.rsplit_once('.').unwrap();
+        assert!(auth.verify(&format!("{}.{}", head, "c2lnbmF0dXJl")).is_err());
+    }
+}
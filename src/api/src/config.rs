@@ -0,0 +1,425 @@
+use crate::rate_limit::{READ_LIMIT, WINDOW, WRITE_LIMIT};
+use crate::roles::default_roles_path;
+use port_manager_core::{config_dir, default_pools_path, state_dir};
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+
+pub const USAGE: &str = "usage: api [--config FILE] [--bind ADDR]... [--data-dir DIR] [--legacy-json FILE] [--roles-file FILE] [--pools-file FILE] [--read-limit N] \
+[--write-limit N] [--rate-window SECS] [--tls-cert FILE --tls-key FILE] [useradd <username> [role]]";
+
+/// Server settings. Each layer overrides the one before: built-in defaults, the TOML
+/// file, `PORTMGR_*` environment variables, then command-line flags.
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// `host:port` to listen on over TCP, or `unix:/path` (or just an absolute path)
+    /// for a Unix socket
+    pub bind: Vec<String>,
+    /// Where `reservations.db` and `users.db` live
+    pub data_dir: PathBuf,
+    /// Reservations file of older versions, imported once at startup; relative paths are
+    /// taken from the working directory, where those versions kept it
+    pub legacy_json: PathBuf,
+    /// Role overrides (see `roles::load_roles`)
+    pub roles_file: PathBuf,
+    /// Pool definitions (see `port_manager_core::load_pools`)
+    pub pools_file: PathBuf,
+    pub rate_limit: RateLimitConfig,
+    /// Key that signs tokens; a random one per start when missing
+    pub jwt_secret: Option<String>,
+    /// Serve TCP binds over HTTPS
+    pub tls: Option<TlsConfig>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimitConfig {
+    /// Requests per window for endpoints that change state
+    pub write: u32,
+    /// Requests per window for everything else
+    pub read: u32,
+    pub window_secs: u64,
+}
+
+/// PEM files for the server certificate chain and its private key
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TlsConfig {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+}
+
+/// One listener from `bind`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Bind {
+    Tcp(String),
+    Unix(PathBuf),
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            bind: vec!["127.0.0.1:8080".to_string()],
+            data_dir: state_dir(),
+            legacy_json: PathBuf::from("reservations.json"),
+            roles_file: default_roles_path(),
+            pools_file: default_pools_path(),
+            rate_limit: RateLimitConfig::default(),
+            jwt_secret: None,
+            tls: None,
+        }
+    }
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        RateLimitConfig { write: WRITE_LIMIT, read: READ_LIMIT, window_secs: WINDOW.as_secs() }
+    }
+}
+
+/// The config file read unless `--config` or `PORTMGR_CONFIG` name another
+pub fn default_config_path() -> PathBuf {
+    config_dir().join("api.toml")
+}
+
+fn parse<T: std::str::FromStr>(name: &str, value: &str) -> Result<T, String> {
+    value.parse().map_err(|_| format!("{}: '{}' is not a valid value", name, value))
+}
+
+impl Config {
+    /// Build the configuration from `args` (without the program name) and the process
+    /// environment. Returns it with the arguments left over after the flags.
+    pub fn load(args: &[String]) -> Result<(Config, Vec<String>), String> {
+        Config::load_from(args, |name| std::env::var(name).ok().filter(|v| !v.is_empty()))
+    }
+
+    /// `load`, with `var` standing in for the environment
+    fn load_from(args: &[String], var: impl Fn(&str) -> Option<String>) -> Result<(Config, Vec<String>), String> {
+        let flags = Flags::parse(args)?;
+        let (path, explicit) = match flags.config.clone().or_else(|| var("PORTMGR_CONFIG").map(PathBuf::from)) {
+            Some(path) => (path, true),
+            None => (default_config_path(), false),
+        };
+        let mut config = Config::from_file(&path, explicit)?;
+        config.apply_env(var)?;
+        config.apply_flags(&flags);
+        config.validate()?;
+        Ok((config, flags.rest))
+    }
+
+    /// Read `path`; a missing file is only an error when it was asked for by name
+    fn from_file(path: &Path, explicit: bool) -> Result<Config, String> {
+        if !explicit && !path.exists() {
+            return Ok(Config::default());
+        }
+        let data = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        toml::from_str(&data).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    fn apply_env(&mut self, var: impl Fn(&str) -> Option<String>) -> Result<(), String> {
+        if let Some(bind) = var("PORTMGR_BIND") {
+            self.bind = bind.split(',').map(str::trim).filter(|b| !b.is_empty()).map(String::from).collect();
+        }
+        if let Some(dir) = var("PORTMGR_DATA_DIR") {
+            self.data_dir = PathBuf::from(dir);
+        }
+        if let Some(path) = var("PORTMGR_LEGACY_JSON") {
+            self.legacy_json = PathBuf::from(path);
+        }
+        if let Some(path) = var("PORTMGR_ROLES_FILE") {
+            self.roles_file = PathBuf::from(path);
+        }
+        if let Some(path) = var("PORTMGR_POOLS_FILE") {
+            self.pools_file = PathBuf::from(path);
+        }
+        if let Some(n) = var("PORTMGR_READ_LIMIT") {
+            self.rate_limit.read = parse("PORTMGR_READ_LIMIT", &n)?;
+        }
+        if let Some(n) = var("PORTMGR_WRITE_LIMIT") {
+            self.rate_limit.write = parse("PORTMGR_WRITE_LIMIT", &n)?;
+        }
+        if let Some(n) = var("PORTMGR_RATE_WINDOW") {
+            self.rate_limit.window_secs = parse("PORTMGR_RATE_WINDOW", &n)?;
+        }
+        if let Some(secret) = var("PORTMGR_JWT_SECRET") {
+            self.jwt_secret = Some(secret);
+        }
+        self.set_tls(var("PORTMGR_TLS_CERT").map(PathBuf::from), var("PORTMGR_TLS_KEY").map(PathBuf::from));
+        Ok(())
+    }
+
+    fn apply_flags(&mut self, flags: &Flags) {
+        if !flags.bind.is_empty() {
+            self.bind = flags.bind.clone();
+        }
+        if let Some(dir) = &flags.data_dir {
+            self.data_dir = dir.clone();
+        }
+        if let Some(path) = &flags.legacy_json {
+            self.legacy_json = path.clone();
+        }
+        if let Some(path) = &flags.roles_file {
+            self.roles_file = path.clone();
+        }
+        if let Some(path) = &flags.pools_file {
+            self.pools_file = path.clone();
+        }
+        if let Some(n) = flags.read_limit {
+            self.rate_limit.read = n;
+        }
+        if let Some(n) = flags.write_limit {
+            self.rate_limit.write = n;
+        }
+        if let Some(n) = flags.rate_window {
+            self.rate_limit.window_secs = n;
+        }
+        self.set_tls(flags.tls_cert.clone(), flags.tls_key.clone());
+    }
+
+    /// Override either TLS path; `validate` catches a certificate without a key
+    fn set_tls(&mut self, cert: Option<PathBuf>, key: Option<PathBuf>) {
+        if cert.is_none() && key.is_none() {
+            return;
+        }
+        let current = self.tls.take();
+        let cert = cert.or_else(|| current.as_ref().map(|t| t.cert.clone())).unwrap_or_default();
+        let key = key.or_else(|| current.map(|t| t.key)).unwrap_or_default();
+        self.tls = Some(TlsConfig { cert, key });
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.bind.is_empty() {
+            return Err("Nothing to listen on; give at least one bind address.".to_string());
+        }
+        if let Some(tls) = &self.tls {
+            if tls.cert.as_os_str().is_empty() || tls.key.as_os_str().is_empty() {
+                return Err("TLS needs both a certificate and a key.".to_string());
+            }
+        }
+        if self.rate_limit.window_secs == 0 {
+            return Err("The rate limit window must be at least a second.".to_string());
+        }
+        Ok(())
+    }
+
+    pub fn binds(&self) -> Vec<Bind> {
+        self.bind
+            .iter()
+            .map(|b| match b.strip_prefix("unix:") {
+                Some(path) => Bind::Unix(PathBuf::from(path)),
+                None if b.starts_with('/') => Bind::Unix(PathBuf::from(b)),
+                None => Bind::Tcp(b.clone()),
+            })
+            .collect()
+    }
+}
+
+/// Command-line flags, which may come before or after a subcommand
+#[derive(Default)]
+struct Flags {
+    config: Option<PathBuf>,
+    bind: Vec<String>,
+    data_dir: Option<PathBuf>,
+    legacy_json: Option<PathBuf>,
+    roles_file: Option<PathBuf>,
+    pools_file: Option<PathBuf>,
+    read_limit: Option<u32>,
+    write_limit: Option<u32>,
+    rate_window: Option<u64>,
+    tls_cert: Option<PathBuf>,
+    tls_key: Option<PathBuf>,
+    rest: Vec<String>,
+}
+
+impl Flags {
+    fn parse(args: &[String]) -> Result<Flags, String> {
+        let mut flags = Flags::default();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let (name, inline) = match arg.split_once('=') {
+                Some((name, value)) if name.starts_with("--") => (name, Some(value.to_string())),
+                _ => (arg.as_str(), None),
+            };
+            if !name.starts_with("--") {
+                flags.rest.push(arg.clone());
+                continue;
+            }
+            if name == "--help" {
+                return Err(USAGE.to_string());
+            }
+            let value = match inline.or_else(|| args.next().cloned()) {
+                Some(value) => value,
+                None => return Err(format!("{} needs a value\n{}", name, USAGE)),
+            };
+            match name {
+                "--config" => flags.config = Some(PathBuf::from(value)),
+                "--bind" => flags.bind.push(value),
+                "--data-dir" => flags.data_dir = Some(PathBuf::from(value)),
+                "--legacy-json" => flags.legacy_json = Some(PathBuf::from(value)),
+                "--roles-file" => flags.roles_file = Some(PathBuf::from(value)),
+                "--pools-file" => flags.pools_file = Some(PathBuf::from(value)),
+                "--read-limit" => flags.read_limit = Some(parse(name, &value)?),
+                "--write-limit" => flags.write_limit = Some(parse(name, &value)?),
+                "--rate-window" => flags.rate_window = Some(parse(name, &value)?),
+                "--tls-cert" => flags.tls_cert = Some(PathBuf::from(value)),
+                "--tls-key" => flags.tls_key = Some(PathBuf::from(value)),
+                _ => return Err(format!("Unknown flag {}\n{}", name, USAGE)),
+            }
+        }
+        Ok(flags)
+    }
+}
+
+/// Load the certificate chain and key named by `tls`
+#[cfg(feature = "tls")]
+pub fn rustls_config(tls: &TlsConfig) -> Result<rustls::ServerConfig, String> {
+    use std::fs::File;
+    use std::io::BufReader;
+    let open = |path: &Path| File::open(path).map(BufReader::new).map_err(|e| format!("{}: {}", path.display(), e));
+    let certs = rustls_pemfile::certs(&mut open(&tls.cert)?)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("{}: {}", tls.cert.display(), e))?;
+    let key = rustls_pemfile::private_key(&mut open(&tls.key)?)
+        .map_err(|e| format!("{}: {}", tls.key.display(), e))?
+        .ok_or_else(|| format!("{}: no private key found", tls.key.display()))?;
+    let provider = std::sync::Arc::new(rustls::crypto::ring::default_provider());
+    rustls::ServerConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .map_err(|e| e.to_string())?
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .map_err(|e| format!("{}: {}", tls.cert.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    const FILE: &str = r#"
+        bind = ["0.0.0.0:9000", "unix:/run/pm.sock"]
+        data_dir = "/srv/file"
+        roles_file = "/etc/file/roles.toml"
+        pools_file = "/etc/file/pools.toml"
+
+        [rate_limit]
+        write = 5
+        read = 50
+    "#;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|a| a.to_string()).collect()
+    }
+
+    /// Load with `toml` as the config file, `env` as the whole environment and `flags`
+    fn load(toml: &str, env: &[(&str, &str)], flags: &[&str]) -> Result<(Config, Vec<String>), String> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("api.toml");
+        std::fs::write(&path, toml).unwrap();
+        let mut env: HashMap<String, String> = env.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        env.entry("PORTMGR_CONFIG".to_string()).or_insert_with(|| path.display().to_string());
+        Config::load_from(&args(flags), |name| env.get(name).cloned())
+    }
+
+    #[test]
+    fn defaults_fill_in_what_the_file_leaves_out() {
+        let (config, rest) = load("", &[], &[]).unwrap();
+        assert_eq!(config.bind, ["127.0.0.1:8080"]);
+        assert_eq!(config.data_dir, state_dir());
+        assert_eq!(config.roles_file, default_roles_path());
+        assert_eq!(config.pools_file, default_pools_path());
+        assert_eq!(config.legacy_json, PathBuf::from("reservations.json"));
+        assert_eq!((config.rate_limit.write, config.rate_limit.read), (WRITE_LIMIT, READ_LIMIT));
+        assert!(config.tls.is_none() && config.jwt_secret.is_none());
+        assert!(rest.is_empty());
+    }
+
+    #[test]
+    fn file_then_environment_then_flags() {
+        let (config, _) = load(FILE, &[], &[]).unwrap();
+        assert_eq!(config.bind, ["0.0.0.0:9000", "unix:/run/pm.sock"]);
+        assert_eq!(config.data_dir, PathBuf::from("/srv/file"));
+        assert_eq!(config.roles_file, PathBuf::from("/etc/file/roles.toml"));
+        assert_eq!(config.pools_file, PathBuf::from("/etc/file/pools.toml"));
+        assert_eq!((config.rate_limit.write, config.rate_limit.read, config.rate_limit.window_secs), (5, 50, 60));
+
+        let env = [
+            ("PORTMGR_BIND", "127.0.0.1:1, 127.0.0.1:2"),
+            ("PORTMGR_DATA_DIR", "/srv/env"),
+            ("PORTMGR_ROLES_FILE", "/etc/env/roles.toml"),
+            ("PORTMGR_POOLS_FILE", "/etc/env/pools.toml"),
+            ("PORTMGR_WRITE_LIMIT", "7"),
+        ];
+        let (config, _) = load(FILE, &env, &[]).unwrap();
+        assert_eq!(config.bind, ["127.0.0.1:1", "127.0.0.1:2"]);
+        assert_eq!(config.data_dir, PathBuf::from("/srv/env"));
+        assert_eq!(config.roles_file, PathBuf::from("/etc/env/roles.toml"));
+        assert_eq!(config.pools_file, PathBuf::from("/etc/env/pools.toml"));
+        // Settings the environment leaves alone still come from the file
+        assert_eq!((config.rate_limit.write, config.rate_limit.read), (7, 50));
+
+        let flags = [
+            "--bind", "127.0.0.1:3", "--data-dir=/srv/flag", "--roles-file", "/etc/flag/roles.toml",
+            "--pools-file", "/etc/flag/pools.toml", "--read-limit", "9",
+        ];
+        let (config, _) = load(FILE, &env, &flags).unwrap();
+        assert_eq!(config.bind, ["127.0.0.1:3"]);
+        assert_eq!(config.data_dir, PathBuf::from("/srv/flag"));
+        assert_eq!(config.roles_file, PathBuf::from("/etc/flag/roles.toml"));
+        assert_eq!(config.pools_file, PathBuf::from("/etc/flag/pools.toml"));
+        assert_eq!((config.rate_limit.write, config.rate_limit.read), (7, 9));
+    }
+
+    #[test]
+    fn config_flag_beats_config_variable() {
+        let dir = tempfile::tempdir().unwrap();
+        let chosen = dir.path().join("chosen.toml");
+        std::fs::write(&chosen, "data_dir = \"/srv/chosen\"").unwrap();
+        let flags = ["--config".to_string(), chosen.display().to_string()];
+        let flags: Vec<&str> = flags.iter().map(String::as_str).collect();
+        let (config, _) = load("data_dir = \"/srv/ignored\"", &[], &flags).unwrap();
+        assert_eq!(config.data_dir, PathBuf::from("/srv/chosen"));
+        let missing = dir.path().join("missing.toml").display().to_string();
+        assert!(load("", &[("PORTMGR_CONFIG", &missing)], &[]).is_err());
+    }
+
+    #[test]
+    fn tls_parts_combine_across_layers() {
+        let toml = "[tls]\ncert = \"/etc/file/cert.pem\"\nkey = \"/etc/file/key.pem\"";
+        let (config, _) = load(toml, &[("PORTMGR_TLS_KEY", "/etc/env/key.pem")], &[]).unwrap();
+        let tls = config.tls.unwrap();
+        assert_eq!((tls.cert, tls.key), (PathBuf::from("/etc/file/cert.pem"), PathBuf::from("/etc/env/key.pem")));
+        assert!(load("", &[], &["--tls-cert", "cert.pem"]).is_err());
+    }
+
+    #[test]
+    fn rejects_bad_settings() {
+        assert!(load("port = 8080", &[], &[]).is_err());
+        assert!(load("bind = []", &[], &[]).is_err());
+        assert!(load("[rate_limit]\nwindow_secs = 0", &[], &[]).is_err());
+        assert!(load("", &[("PORTMGR_READ_LIMIT", "lots")], &[]).is_err());
+        assert!(load("", &[], &["--write-limit", "-1"]).is_err());
+        assert!(load("", &[], &["--verbose"]).is_err());
+        assert!(load("", &[], &["--bind"]).is_err());
+    }
+
+    #[test]
+    fn leaves_subcommands_for_the_caller() {
+        let (config, rest) = load("", &[], &["useradd", "--data-dir", "/srv/users", "alice", "admin"]).unwrap();
+        assert_eq!(rest, ["useradd", "alice", "admin"]);
+        assert_eq!(config.data_dir, PathBuf::from("/srv/users"));
+    }
+
+    #[test]
+    fn tells_unix_sockets_from_tcp() {
+        let (config, _) = load("bind = [\"[::1]:8080\", \"unix:/run/a.sock\", \"/run/b.sock\"]", &[], &[]).unwrap();
+        assert_eq!(
+            config.binds(),
+            [
+                Bind::Tcp("[::1]:8080".to_string()),
+                Bind::Unix(PathBuf::from("/run/a.sock")),
+                Bind::Unix(PathBuf::from("/run/b.sock")),
+            ]
+        );
+    }
+}
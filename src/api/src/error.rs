@@ -0,0 +1,161 @@
+use std::fmt;
+use actix_web::error::{JsonPayloadError, PathError, QueryPayloadError};
+use actix_web::http::StatusCode;
+use actix_web::{HttpRequest, HttpResponse, ResponseError};
+use port_manager_core::ReservationError;
+use serde::Serialize;
+use validator::ValidationErrors;
+
+/// Error body shared by every endpoint: `{"code": "NOT_FOUND", "message": "..."}`.
+/// `code` is stable for clients to match on; `message` is for people.
+#[derive(Debug, Serialize, utoipa::ToSchema)]
+pub struct ApiError {
+    #[serde(skip)]
+    pub status: StatusCode,
+    #[schema(example = "NOT_FOUND")]
+    pub code: &'static str,
+    pub message: String,
+}
+
+impl ApiError {
+    pub fn new(status: StatusCode, code: &'static str, message: impl Into<String>) -> Self {
+        ApiError { status, code, message: message.into() }
+    }
+
+    pub fn bad_request(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, "INVALID_REQUEST", message)
+    }
+
+    pub fn unauthorized(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::UNAUTHORIZED, "UNAUTHORIZED", message)
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        self.status
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status).json(self)
+    }
+}
+
+impl From<ReservationError> for ApiError {
+    fn from(e: ReservationError) -> Self {
+        let status = match e {
+            ReservationError::AlreadyReserved(_)
+            | ReservationError::InUse(_)
+            | ReservationError::LimitReached(_)
+            | ReservationError::NoFreePort(_) => StatusCode::CONFLICT,
+            ReservationError::NotFound(_) => StatusCode::NOT_FOUND,
+            ReservationError::Forbidden(_) => StatusCode::FORBIDDEN,
+            ReservationError::Invalid(_) => StatusCode::BAD_REQUEST,
+            ReservationError::Storage(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        Self::new(status, e.code(), e.message())
+    }
+}
+
+/// One `field: problem` clause per failed check, e.g. `service: must not be empty`
+impl From<ValidationErrors> for ApiError {
+    fn from(errors: ValidationErrors) -> Self {
+        let mut problems: Vec<String> = errors
+            .field_errors()
+            .into_iter()
+            .flat_map(|(field, errs)| {
+                errs.iter().map(move |e| match &e.message {
+                    Some(message) => format!("{}: {}", field, message),
+                    None => format!("{}: failed '{}' check", field, e.code),
+                })
+            })
+            .collect();
+        problems.sort();
+        Self::bad_request(problems.join("; "))
+    }
+}
+
+/// Malformed bodies, queries and paths get the same envelope as every other error
+pub fn json_error(err: JsonPayloadError, _req: &HttpRequest) -> actix_web::Error {
+    ApiError::bad_request(err.to_string()).into()
+}
+
+pub fn query_error(err: QueryPayloadError, _req: &HttpRequest) -> actix_web::Error {
+    ApiError::bad_request(err.to_string()).into()
+}
+
+pub fn path_error(err: PathError, _req: &HttpRequest) -> actix_web::Error {
+    ApiError::bad_request(err.to_string()).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::{call_service, init_service, read_body_json, TestRequest};
+    use actix_web::{web, App};
+    use serde::Deserialize;
+    use serde_json::{json, Value};
+
+    #[actix_web::test]
+    async fn maps_every_store_error() {
+        let cases = [
+            (ReservationError::AlreadyReserved("m".into()), StatusCode::CONFLICT, "ALREADY_RESERVED"),
+            (ReservationError::InUse("m".into()), StatusCode::CONFLICT, "IN_USE"),
+            (ReservationError::NotFound("m".into()), StatusCode::NOT_FOUND, "NOT_FOUND"),
+            (ReservationError::Forbidden("m".into()), StatusCode::FORBIDDEN, "FORBIDDEN"),
+            (ReservationError::LimitReached("m".into()), StatusCode::CONFLICT, "LIMIT_REACHED"),
+            (ReservationError::NoFreePort("m".into()), StatusCode::CONFLICT, "NO_FREE_PORT"),
+            (ReservationError::Invalid("m".into()), StatusCode::BAD_REQUEST, "INVALID_REQUEST"),
+            (ReservationError::Storage("m".into()), StatusCode::INTERNAL_SERVER_ERROR, "STORAGE_ERROR"),
+        ];
+        for (error, status, code) in cases {
+            let api = ApiError::from(error);
+            assert_eq!((api.status_code(), api.code, api.message.as_str()), (status, code, "m"));
+            let body = actix_web::body::to_bytes(api.error_response().into_body()).await.unwrap();
+            let body: Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(body, json!({"code": code, "message": "m"}));
+        }
+    }
+
+    #[derive(Deserialize)]
+    struct Port {
+        port: u16,
+    }
+
+    async fn handler(path: web::Path<u16>, query: web::Query<Port>, body: web::Json<Port>) -> HttpResponse {
+        assert_eq!((*path, query.port, body.port), (1, 1, 1));
+        HttpResponse::NoContent().finish()
+    }
+
+    #[actix_web::test]
+    async fn malformed_requests_get_the_envelope() {
+        let app = init_service(
+            App::new()
+                .app_data(web::JsonConfig::default().error_handler(json_error))
+                .app_data(web::QueryConfig::default().error_handler(query_error))
+                .app_data(web::PathConfig::default().error_handler(path_error))
+                .route("/{port}", web::post().to(handler)),
+        )
+        .await;
+        let ok = TestRequest::post().uri("/1?port=1").set_json(json!({"port": 1}));
+        assert_eq!(call_service(&app, ok.to_request()).await.status(), StatusCode::NO_CONTENT);
+        for req in [
+            TestRequest::post().uri("/http?port=1").set_json(json!({"port": 1})),
+            TestRequest::post().uri("/1?port=x").set_json(json!({"port": 1})),
+            TestRequest::post().uri("/1?port=1").set_json(json!({"port": 70000})),
+            TestRequest::post().uri("/1?port=1").insert_header(("content-type", "application/json")).set_payload("{"),
+        ] {
+            let res = call_service(&app, req.to_request()).await;
+            assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+            let body: Value = read_body_json(res).await;
+            assert_eq!(body["code"], "INVALID_REQUEST");
+            assert!(body["message"].as_str().is_some_and(|m| !m.is_empty()), "{}", body);
+        }
+    }
+}
@@ -0,0 +1,105 @@
+use crate::metrics;
+use crate::filter::PortFilter;
+use actix_web::web;
+use actix_web_lab::sse::{self, ChannelStream, Sse, TrySendError};
+use port_manager_core::{PortEvent, PortReservationManager, PortWatcher};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// How often sockets and reservations are compared while anyone is listening
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// How often idle streams get a comment, which also notices clients that went away
+const PING_INTERVAL: Duration = Duration::from_secs(15);
+/// Events a slow client may fall behind by before it starts missing them
+const CLIENT_BUFFER: usize = 64;
+
+struct Client {
+    filter: PortFilter,
+    tx: sse::Sender,
+}
+
+/// Fans events out to every `/events` stream whose filter matches
+#[derive(Default)]
+pub struct Broadcaster {
+    clients: Mutex<Vec<Client>>,
+}
+
+impl Broadcaster {
+    pub fn subscribe(&self, filter: PortFilter) -> Sse<ChannelStream> {
+        let (tx, stream) = sse::channel(CLIENT_BUFFER);
+        self.clients.lock().unwrap().push(Client { filter, tx });
+        stream
+    }
+
+    fn is_idle(&self) -> bool {
+        self.clients.lock().unwrap().is_empty()
+    }
+
+    /// Send each client the events it asked for, dropping clients that disconnected
+    fn broadcast(&self, events: &[PortEvent]) {
+        self.clients.lock().unwrap().retain(|client| {
+            for event in events.iter().filter(|e| client.filter.matches_event(e)) {
+                let data = match sse::Data::new_json(event) {
+                    Ok(data) => data.event(event.kind()),
+                    Err(e) => {
+                        log::warn!("failed to encode {} event: {}", event.kind(), e);
+                        continue;
+                    }
+                };
+                match client.tx.try_send(data) {
+                    Ok(()) => {}
+                    Err(TrySendError::Closed(_)) => return false,
+                    Err(_) => log::warn!("event stream is full; dropping a {} event", event.kind()),
+                }
+            }
+            true
+        });
+    }
+
+    fn ping(&self) {
+        self.clients
+            .lock()
+            .unwrap()
+            .retain(|client| !matches!(client.tx.try_send(sse::Event::Comment("ping".into())), Err(TrySendError::Closed(_))));
+    }
+}
+
+/// Diff `list_ports` and the reservation store every `POLL_INTERVAL` and broadcast the
+/// changes. Nothing is polled while no stream is open; the first poll after that only
+/// takes a snapshot.
+pub fn spawn_watcher(broadcaster: web::Data<Broadcaster>, manager: Arc<PortReservationManager>) {
+    actix_web::rt::spawn(async move {
+        let mut interval = actix_web::rt::time::interval(POLL_INTERVAL);
+        let pings_every = (PING_INTERVAL.as_secs() / POLL_INTERVAL.as_secs()).max(1);
+        let mut watcher: Option<PortWatcher> = None;
+        let mut ticks = 0u64;
+        loop {
+            interval.tick().await;
+            if broadcaster.is_idle() {
+                watcher = None;
+                continue;
+            }
+            let mut ports = match web::block(metrics::list_ports).await {
+                Ok(ports) => ports,
+                Err(e) => {
+                    log::warn!("failed to list ports for events: {}", e);
+                    continue;
+                }
+            };
+            manager.tag_reserved(&mut ports);
+            let reservations = manager.get_all_reservations();
+            match watcher.as_mut() {
+                Some(watcher) => broadcaster.broadcast(&watcher.update(ports, reservations)),
+                None => {
+                    let mut first = PortWatcher::new();
+                    first.update(ports, reservations);
+                    watcher = Some(first);
+                }
+            }
+            ticks += 1;
+            if ticks.is_multiple_of(pings_every) {
+                broadcaster.ping();
+            }
+        }
+    });
+}
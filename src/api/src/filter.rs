@@ -0,0 +1,242 @@
+use crate::error::ApiError;
+use port_manager_core::{PortEvent, PortInfo, Protocol, SortBy};
+use serde::Deserialize;
+use utoipa::{IntoParams, ToSchema};
+
+/// Filters for `/ports` and `/events`; every given filter must match. `protocol`, `state`,
+/// `user` and `process_name` take comma-separated alternatives.
+#[derive(Deserialize, IntoParams, ToSchema)]
+#[into_params(parameter_in = Query)]
+pub struct PortFilter {
+    /// Substring of the addresses, state, PID or process name, like the TUI's search
+    query: Option<String>,
+    #[param(example = "TCP")]
+    protocol: Option<String>,
+    /// `TIME_WAIT` and `TimeWait` are the same state
+    #[param(example = "LISTEN,ESTABLISHED")]
+    state: Option<String>,
+    /// Lowest port, inclusive
+    port_start: Option<u16>,
+    /// Highest port, inclusive
+    port_end: Option<u16>,
+    /// Comma-separated; a socket must carry every one
+    #[param(example = "reserved,loopback-only")]
+    tags: Option<String>,
+    user: Option<String>,
+    /// Exact process name, ignoring case
+    #[param(example = "nginx,postgres")]
+    process_name: Option<String>,
+}
+
+/// The non-empty values of a comma-separated filter
+pub fn values(list: &str) -> impl Iterator<Item = &str> {
+    list.split(',').map(str::trim).filter(|v| !v.is_empty())
+}
+
+/// Whether a comma-separated filter is absent or one of its values satisfies `f`
+fn any_of(list: Option<&str>, f: impl Fn(&str) -> bool) -> bool {
+    list.is_none_or(|list| values(list).any(f))
+}
+
+/// `LISTEN` matches `Listen` and `TIME_WAIT` matches `TimeWait`
+fn same_state(a: &str, b: &str) -> bool {
+    let normalize = |s: &str| s.chars().filter(|c| *c != '_').collect::<String>().to_lowercase();
+    normalize(a) == normalize(b)
+}
+
+impl PortFilter {
+    /// The protocol and port range, the only filters reservations can be held to
+    fn matches_port(&self, protocol: Protocol, port: u16) -> bool {
+        any_of(self.protocol.as_deref(), |proto| protocol.as_str().eq_ignore_ascii_case(proto))
+            && self.port_start.is_none_or(|start| port >= start)
+            && self.port_end.is_none_or(|end| port <= end)
+    }
+
+    pub fn matches(&self, p: &PortInfo) -> bool {
+        self.matches_port(p.protocol, p.port)
+            && any_of(self.state.as_deref(), |state| same_state(&p.state, state))
+            && any_of(self.user.as_deref(), |user| p.user.as_deref() == Some(user))
+            && any_of(self.process_name.as_deref(), |name| {
+                p.process.as_deref().is_some_and(|process| process.eq_ignore_ascii_case(name))
+            })
+            && self.tags.as_deref().is_none_or(|tags| values(tags).all(|tag| p.tags.iter().any(|have| have == tag)))
+            && self.query.as_deref().is_none_or(|query| p.matches(query))
+    }
+
+    /// Socket events go through `matches`; reservations only have a protocol and port to match
+    pub fn matches_event(&self, event: &PortEvent) -> bool {
+        match event {
+            PortEvent::SocketOpened { socket }
+            | PortEvent::SocketClosed { socket }
+            | PortEvent::StateChanged { socket, .. } => self.matches(socket),
+            PortEvent::Reserved { .. } | PortEvent::Released { .. } => {
+                let (protocol, port) = event.port();
+                self.matches_port(protocol, port)
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Default, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Order {
+    #[default]
+    Asc,
+    Desc,
+}
+
+/// Fields of a socket that `?fields=` can pick
+const PORT_FIELDS: &[&str] = &[
+    "local_addr", "remote_addr", "state", "pid", "process_name", "protocol", "port", "tags", "user",
+];
+
+/// Order, page and trim the `/ports` listing
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct PageQuery {
+    /// Defaults to `port`; ties are kept in port order
+    #[serde(default)]
+    #[param(inline)]
+    pub sort: SortBy,
+    #[serde(default)]
+    #[param(inline)]
+    pub order: Order,
+    /// Most sockets to return; all of them if missing
+    pub limit: Option<usize>,
+    /// Sockets to skip, after filtering and sorting
+    #[serde(default)]
+    pub offset: usize,
+    /// Comma-separated socket fields to return; all of them if missing
+    #[param(example = "port,pid,process_name")]
+    fields: Option<String>,
+}
+
+impl PageQuery {
+    /// The requested fields, refusing names a socket doesn't have
+    pub fn fields(&self) -> Result<Option<Vec<&str>>, ApiError> {
+        let Some(fields) = self.fields.as_deref() else { return Ok(None) };
+        let fields: Vec<&str> = values(fields).collect();
+        if let Some(unknown) = fields.iter().find(|f| !PORT_FIELDS.contains(f)) {
+            return Err(ApiError::bad_request(format!(
+                "Unknown field '{}'; expected some of {}.",
+                unknown,
+                PORT_FIELDS.join(", ")
+            )));
+        }
+        Ok(Some(fields))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::web::Query;
+    use port_manager_core::Reservation;
+
+    fn filter(query: &str) -> PortFilter {
+        Query::<PortFilter>::from_query(query).unwrap().into_inner()
+    }
+
+    fn socket(protocol: Protocol, port: u16, state: &str, process: &str) -> PortInfo {
+        PortInfo {
+            local_addr: format!("127.0.0.1:{}", port),
+            remote_addr: "0.0.0.0:0".to_string(),
+            state: state.to_string(),
+            pid: Some(42),
+            process: Some(process.to_string()),
+            protocol,
+            port,
+            tags: vec!["reserved".to_string(), "loopback-only".to_string()],
+            user: Some("alice".to_string()),
+        }
+    }
+
+    #[test]
+    fn takes_comma_separated_alternatives() {
+        let nginx = socket(Protocol::Tcp, 80, "Listen", "nginx");
+        let dns = socket(Protocol::Udp, 53, "", "dnsmasq");
+        assert!(filter("protocol=tcp,udp").matches(&nginx) && filter("protocol=tcp,udp").matches(&dns));
+        assert!(!filter("protocol=UDP").matches(&nginx));
+        assert!(filter("process_name=postgres,NGINX").matches(&nginx));
+        assert!(!filter("process_name=ngin").matches(&nginx));
+        assert!(filter("user=bob,alice").matches(&nginx));
+        assert!(!filter("user=bob").matches(&nginx));
+        // Empty alternatives are ignored rather than matching everything
+        assert!(filter("state=,listen,").matches(&nginx));
+        assert!(!filter("state=,established,").matches(&nginx));
+    }
+
+    #[test]
+    fn states_ignore_case_and_underscores() {
+        let waiting = socket(Protocol::Tcp, 8080, "TimeWait", "curl");
+        for state in ["TimeWait", "TIME_WAIT", "time_wait", "timewait", "LISTEN,time_wait"] {
+            assert!(filter(&format!("state={}", state)).matches(&waiting), "{}", state);
+        }
+        assert!(!filter("state=TIME").matches(&waiting));
+        assert!(!filter("state=CLOSE_WAIT").matches(&waiting));
+    }
+
+    #[test]
+    fn port_bounds_may_be_open_ended() {
+        let low = socket(Protocol::Tcp, 80, "Listen", "nginx");
+        let high = socket(Protocol::Tcp, 8080, "Listen", "java");
+        let from_1024 = filter("port_start=1024");
+        assert!(!from_1024.matches(&low) && from_1024.matches(&high));
+        let up_to_1023 = filter("port_end=1023");
+        assert!(up_to_1023.matches(&low) && !up_to_1023.matches(&high));
+        let exact = filter("port_start=8080&port_end=8080");
+        assert!(!exact.matches(&low) && exact.matches(&high));
+        assert!(Query::<PortFilter>::from_query("port_start=70000").is_err());
+        assert!(Query::<PortFilter>::from_query("port_end=high").is_err());
+    }
+
+    #[test]
+    fn needs_every_tag_and_the_search_text() {
+        let nginx = socket(Protocol::Tcp, 80, "Listen", "nginx");
+        assert!(filter("tags=reserved,loopback-only").matches(&nginx));
+        assert!(!filter("tags=reserved,unreserved").matches(&nginx));
+        assert!(filter("query=NGINX").matches(&nginx));
+        assert!(!filter("query=postgres").matches(&nginx));
+        assert!(filter("").matches(&nginx));
+    }
+
+    #[test]
+    fn holds_reservation_events_to_protocol_and_port_only() {
+        let reservation = Reservation {
+            port: 8080,
+            protocol: Protocol::Tcp,
+            bind_addr: None,
+            service: "web".to_string(),
+            owner: None,
+            created_at: 0,
+            expires_at: None,
+            ttl_secs: None,
+            pool: None,
+            notes: None,
+        };
+        let event = PortEvent::Reserved { reservation };
+        assert!(filter("protocol=tcp&port_start=8000&state=LISTEN&process_name=none").matches_event(&event));
+        assert!(!filter("protocol=udp").matches_event(&event));
+        assert!(!filter("port_end=8000").matches_event(&event));
+        let opened = PortEvent::SocketOpened { socket: socket(Protocol::Tcp, 8080, "Listen", "nginx") };
+        assert!(!filter("process_name=none").matches_event(&opened));
+    }
+
+    #[test]
+    fn pages_with_known_fields_only() {
+        let page = Query::<PageQuery>::from_query("").unwrap().into_inner();
+        assert_eq!((page.sort, page.limit, page.offset), (SortBy::Port, None, 0));
+        assert!(matches!(page.order, Order::Asc));
+        assert_eq!(page.fields().unwrap(), None);
+
+        let page = Query::<PageQuery>::from_query("sort=pid&order=desc&limit=5&offset=10&fields=port,,pid").unwrap();
+        assert_eq!((page.sort, page.limit, page.offset), (SortBy::Pid, Some(5), 10));
+        assert!(matches!(page.order, Order::Desc));
+        assert_eq!(page.fields().unwrap(), Some(vec!["port", "pid"]));
+
+        let unknown = Query::<PageQuery>::from_query("fields=port,password").unwrap();
+        assert_eq!(unknown.fields().unwrap_err().code, "INVALID_REQUEST");
+        assert!(Query::<PageQuery>::from_query("sort=size").is_err());
+        assert!(Query::<PageQuery>::from_query("order=sideways").is_err());
+    }
+}
@@ -0,0 +1,105 @@
+use actix_web::web;
+use lazy_static::lazy_static;
+use port_manager_core::{net, PortInfo, PortReservationManager, Protocol};
+use prometheus::{
+    register_histogram, register_int_gauge, register_int_gauge_vec, Histogram, IntGauge, IntGaugeVec,
+};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// How often the port gauges are brought up to date
+const REFRESH_INTERVAL: Duration = Duration::from_secs(15);
+
+lazy_static! {
+    static ref SOCKETS: IntGaugeVec = register_int_gauge_vec!(
+        "port_manager_sockets", "Sockets with an owning process, by protocol and state", &["protocol", "state"]
+    ).unwrap();
+    static ref RESERVATIONS: IntGaugeVec = register_int_gauge_vec!(
+        "port_manager_reservations", "Live reservations by pool (\"none\" outside pools)", &["pool"]
+    ).unwrap();
+    static ref POOL_CAPACITY: IntGaugeVec = register_int_gauge_vec!(
+        "port_manager_pool_capacity", "Ports in each configured pool's range", &["pool"]
+    ).unwrap();
+    static ref RESERVED_UNBOUND: IntGauge = register_int_gauge!(
+        "port_manager_reserved_unbound", "Live reservations no socket is bound to"
+    ).unwrap();
+    static ref BOUND_UNRESERVED: IntGauge = register_int_gauge!(
+        "port_manager_bound_unreserved", "Listening TCP and bound UDP addresses no reservation covers"
+    ).unwrap();
+    static ref TIME_WAIT: IntGauge = register_int_gauge!(
+        "port_manager_time_wait_sockets", "TCP sockets in TIME_WAIT"
+    ).unwrap();
+    static ref LIST_PORTS_SECONDS: Histogram = register_histogram!(
+        "port_manager_list_ports_duration_seconds", "Time taken to list sockets and their processes",
+        vec![0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0]
+    ).unwrap();
+}
+
+/// `net::list_ports`, timed into the duration histogram
+pub fn list_ports() -> Vec<PortInfo> {
+    let started = Instant::now();
+    let ports = net::list_ports();
+    LIST_PORTS_SECONDS.observe(started.elapsed().as_secs_f64());
+    ports
+}
+
+/// Recompute every gauge from a fresh `list_ports` and the reservation store
+fn refresh(manager: &PortReservationManager) {
+    let mut ports = list_ports();
+    manager.tag_reserved(&mut ports);
+
+    SOCKETS.reset();
+    for p in &ports {
+        SOCKETS.with_label_values(&[p.protocol.as_str(), &p.state]).inc();
+    }
+
+    // Listening TCP and any UDP socket hold their address; a process may hold it more than once
+    let bound: Vec<_> = ports.iter().filter(|p| p.protocol == Protocol::Udp || p.state == "Listen").collect();
+    let unreserved: HashSet<_> = bound
+        .iter()
+        .filter(|p| !p.tags.iter().any(|t| t == "reserved"))
+        .map(|p| (p.protocol, p.local_addr.as_str()))
+        .collect();
+    BOUND_UNRESERVED.set(unreserved.len() as i64);
+
+    let reservations = manager.get_all_reservations();
+    // Every configured pool gets a series, so an empty one reads 0 rather than missing
+    let mut per_pool: HashMap<&str, i64> = manager.pools().iter().map(|p| (p.name.as_str(), 0)).collect();
+    per_pool.insert("none", 0);
+    for r in &reservations {
+        *per_pool.entry(r.pool.as_deref().unwrap_or("none")).or_default() += 1;
+    }
+    RESERVATIONS.reset();
+    for (pool, count) in per_pool {
+        RESERVATIONS.with_label_values(&[pool]).set(count);
+    }
+    POOL_CAPACITY.reset();
+    for pool in manager.pools() {
+        let capacity = i64::from(pool.range.end) - i64::from(pool.range.start) + 1;
+        POOL_CAPACITY.with_label_values(&[&pool.name]).set(capacity);
+    }
+    let unbound = reservations
+        .iter()
+        .filter(|r| {
+            !bound.iter().any(|p| p.protocol == r.protocol && p.port == r.port && net::addrs_overlap(r.bind_addr, p.local_ip()))
+        })
+        .count();
+    RESERVED_UNBOUND.set(unbound as i64);
+
+    TIME_WAIT.set(net::time_wait_count() as i64);
+}
+
+/// Keep the port gauges current in the background, off the request workers
+pub fn spawn_refresher(manager: Arc<PortReservationManager>) {
+    actix_web::rt::spawn(async move {
+        let mut interval = actix_web::rt::time::interval(REFRESH_INTERVAL);
+        loop {
+            interval.tick().await;
+            let manager = manager.clone();
+            if let Err(e) = web::block(move || refresh(&manager)).await {
+                log::warn!("failed to refresh port metrics: {}", e);
+            }
+        }
+    });
+}
@@ -0,0 +1,71 @@
+use actix_web::HttpResponse;
+use utoipa::openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::{Modify, OpenApi};
+
+/// The OpenAPI 3 description of `/api/v1`, built from the handler annotations
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "Port Manager API", description = "Reserve, allocate and inspect network ports."),
+    servers((url = "/api/v1")),
+    paths(
+        spec,
+        crate::login,
+        crate::reserve_port,
+        crate::allocate_port,
+        crate::release_port,
+        crate::renew_lease,
+        crate::list_pools,
+        crate::status,
+        crate::list_reservations,
+        crate::get_reservation,
+        crate::update_reservation,
+        crate::delete_reservation,
+        crate::list_ports,
+        crate::events,
+        crate::processes::signal_process,
+        crate::processes::free_port,
+        crate::audit::list_audit,
+    ),
+    components(schemas(crate::filter::PortFilter)),
+    modifiers(&BearerAuth),
+    security(("bearer" = [])),
+)]
+pub struct ApiDoc;
+
+/// Declares the `bearer` scheme that `security` refers to
+struct BearerAuth;
+
+impl Modify for BearerAuth {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let scheme = HttpBuilder::new().scheme(HttpAuthScheme::Bearer).bearer_format("JWT").build();
+        openapi
+            .components
+            .get_or_insert_with(Default::default)
+            .add_security_scheme("bearer", SecurityScheme::Http(scheme));
+    }
+}
+
+/// This OpenAPI description, for generating clients
+#[utoipa::path(get, path = "/openapi.json", tag = "meta", security(), responses((status = 200, description = "OpenAPI 3 JSON")))]
+pub async fn spec() -> HttpResponse {
+    HttpResponse::Ok().json(ApiDoc::openapi())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeSet;
+
+    #[test]
+    fn documents_every_routed_path() {
+        // The path literals registered by `routes`, besides the `/api/v1` scope itself
+        let source = include_str!("main.rs");
+        let routes = &source[source.find("\nfn routes(").unwrap()..];
+        let routes = &routes[..routes.find("\n}\n").unwrap()];
+        let routed: BTreeSet<&str> =
+            routes.split('"').skip(1).step_by(2).filter(|s| s.starts_with('/') && *s != "/api/v1").collect();
+        let spec = ApiDoc::openapi();
+        let documented: BTreeSet<&str> = spec.paths.paths.keys().map(String::as_str).collect();
+        assert_eq!(routed, documented);
+    }
+}
@@ -0,0 +1,44 @@
+use std::any::Any;
+use actix_web::dev::Extensions;
+
+/// Role of root and of the user the server runs as, when they call over a Unix socket
+pub const PEER_ADMIN_ROLE: &str = "admin";
+
+/// Who is on the other end of a Unix socket connection, as the kernel reports it
+/// (`SO_PEERCRED`). Stored in the connection data of every request on that connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PeerCred {
+    pub uid: u32,
+    pub gid: u32,
+    pub pid: Option<i32>,
+}
+
+impl PeerCred {
+    /// Name recorded as owner and actor for this caller
+    pub fn username(&self) -> String {
+        port_manager_core::uid_owner(self.uid)
+    }
+
+    /// Root and the server's own user may do anything; everyone else gets `default_role`
+    pub fn role<'a>(&self, default_role: &'a str) -> &'a str {
+        if self.uid == 0 || self.uid == nix::unistd::geteuid().as_raw() {
+            PEER_ADMIN_ROLE
+        } else {
+            default_role
+        }
+    }
+}
+
+/// `HttpServer::on_connect` hook that records peer credentials of Unix socket
+/// connections. TCP connections carry no credentials and are left alone.
+pub fn on_connect(conn: &dyn Any, data: &mut Extensions) {
+    #[cfg(unix)]
+    if let Some(stream) = conn.downcast_ref::<actix_web::rt::net::UnixStream>() {
+        match stream.peer_cred() {
+            Ok(cred) => {
+                data.insert(PeerCred { uid: cred.uid(), gid: cred.gid(), pid: cred.pid() });
+            }
+            Err(e) => log::warn!("could not read peer credentials: {}", e),
+        }
+    }
+}
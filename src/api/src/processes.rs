@@ -0,0 +1,179 @@
+use crate::audit;
+use crate::auth::AuthUser;
+use crate::error::ApiError;
+use crate::roles::{Forbidden, Permission};
+use crate::AppState;
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpResponse};
+use nix::errno::Errno;
+use nix::sys::signal::{kill, Signal};
+use nix::unistd::Pid;
+use port_manager_core::{is_protected, net, port_owners, AuditEntry, Protocol};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+fn default_signal() -> String {
+    "TERM".to_string()
+}
+
+fn parse_signal(name: &str) -> Result<Signal, ApiError> {
+    port_manager_core::parse_signal(name).map_err(ApiError::bad_request)
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct SignalRequest {
+    /// Signal name, with or without the `SIG` prefix
+    #[serde(default = "default_signal")]
+    #[schema(example = "TERM")]
+    signal: String,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct FreeRequest {
+    #[serde(default)]
+    protocol: Protocol,
+    #[serde(default = "default_signal")]
+    #[schema(example = "TERM")]
+    signal: String,
+}
+
+/// A process that was sent a signal
+#[derive(Serialize, ToSchema)]
+pub struct Signalled {
+    pid: i32,
+    process_name: Option<String>,
+    #[schema(example = "SIGTERM")]
+    signal: String,
+}
+
+/// A process the signal couldn't be sent to
+#[derive(Serialize, ToSchema)]
+pub struct SignalFailure {
+    pid: i32,
+    process_name: Option<String>,
+    #[schema(example = "FORBIDDEN")]
+    code: &'static str,
+    message: String,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct Freed {
+    port: u16,
+    protocol: Protocol,
+    killed: Vec<Signalled>,
+    failed: Vec<SignalFailure>,
+}
+
+/// PID 1 and the server itself are never signalled, and nor are process groups
+fn check_pid(pid: i32) -> Result<(), ApiError> {
+    if is_protected(pid) {
+        return Err(ApiError::new(
+            StatusCode::FORBIDDEN,
+            "PROTECTED_PROCESS",
+            format!("PID {} may not be signalled.", pid),
+        ));
+    }
+    Ok(())
+}
+
+fn send(pid: i32, signal: Signal) -> Result<(), ApiError> {
+    check_pid(pid)?;
+    kill(Pid::from_raw(pid), signal).map_err(|errno| match errno {
+        Errno::ESRCH => ApiError::new(StatusCode::NOT_FOUND, "NOT_FOUND", format!("No process with PID {}.", pid)),
+        Errno::EPERM => ApiError::new(
+            StatusCode::FORBIDDEN,
+            "FORBIDDEN",
+            format!("The server may not signal PID {}.", pid),
+        ),
+        e => ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "INTERNAL", format!("Signalling PID {} failed: {}", pid, e)),
+    })
+}
+
+/// Send a signal to a process
+#[utoipa::path(
+    post, path = "/processes/{pid}/signal", tag = "processes",
+    params(("pid" = i32, Path)),
+    request_body = SignalRequest,
+    responses(
+        (status = 200, body = Signalled),
+        (status = 400, body = ApiError, description = "Unknown signal"),
+        (status = 401, body = ApiError),
+        (status = 403, body = Forbidden, description = "Missing `kill`, or PID 1 or the server itself"),
+        (status = 404, body = ApiError, description = "No such process"),
+    ),
+)]
+pub async fn signal_process(
+    data: web::Data<AppState>,
+    user: AuthUser,
+    pid: web::Path<i32>,
+    body: web::Json<SignalRequest>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let pid = pid.into_inner();
+    let entry = AuditEntry::new(&user.username, "signal").pid(pid).detail(body.signal.clone());
+    let result = user.require(Permission::Kill).map_err(Into::into).and_then(|()| {
+        let signal = parse_signal(&body.signal)?;
+        send(pid, signal)?;
+        Ok(signal)
+    });
+    audit::record_result(&data.manager, entry, &result);
+    let signal = result?;
+    let process_name = net::process_name(pid);
+    Ok(HttpResponse::Ok().json(Signalled { pid, process_name, signal: signal.as_str().to_string() }))
+}
+
+/// Terminate whatever holds a port
+///
+/// Signals every process with a socket bound to the port, refusing all of them if one is
+/// protected. Each process is tried even if another fails; `killed` lists those that
+/// were signalled and `failed` the rest, with the reason in the `ApiError` code.
+#[utoipa::path(
+    post, path = "/ports/{port}/free", tag = "processes",
+    params(("port" = u16, Path)),
+    request_body = FreeRequest,
+    responses(
+        (status = 200, body = Freed),
+        (status = 400, body = ApiError, description = "Unknown signal"),
+        (status = 401, body = ApiError),
+        (status = 403, body = Forbidden, description = "Missing `kill`, or PID 1 or the server holds the port"),
+        (status = 404, body = ApiError, description = "No process we can see holds the port"),
+    ),
+)]
+pub async fn free_port(
+    data: web::Data<AppState>,
+    user: AuthUser,
+    port: web::Path<u16>,
+    body: web::Json<FreeRequest>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let port = port.into_inner();
+    let entry = AuditEntry::new(&user.username, "free").port(body.protocol, port).detail(body.signal.clone());
+    let owners = port_owners(body.protocol, port);
+    let checked = user.require(Permission::Kill).map_err(Into::into).and_then(|()| {
+        let signal = parse_signal(&body.signal)?;
+        if owners.is_empty() {
+            let message = format!("No process holds {} port {}.", body.protocol, port);
+            return Err(ApiError::new(StatusCode::NOT_FOUND, "NOT_FOUND", message).into());
+        }
+        owners.iter().try_for_each(|(pid, _)| check_pid(*pid))?;
+        Ok(signal)
+    });
+    if checked.is_err() {
+        audit::record_result(&data.manager, entry.clone(), &checked);
+    }
+    let signal = checked?;
+    // One entry per process, so each can be traced by PID
+    let (mut killed, mut failed) = (Vec::new(), Vec::new());
+    for (pid, process_name) in owners {
+        let entry = entry.clone().pid(pid);
+        match send(pid, signal) {
+            Ok(()) => {
+                audit::record(&data.manager, entry);
+                killed.push(Signalled { pid, process_name, signal: signal.as_str().to_string() });
+            }
+            Err(e) => {
+                audit::record(&data.manager, entry.failed(e.code, e.message.clone()));
+                failed.push(SignalFailure { pid, process_name, code: e.code, message: e.message });
+            }
+        }
+    }
+    Ok(HttpResponse::Ok().json(Freed { port, protocol: body.protocol, killed, failed }))
+}
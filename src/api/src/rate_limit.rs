@@ -0,0 +1,205 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use actix_web::body::{EitherBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{HeaderName, HeaderValue, AUTHORIZATION, RETRY_AFTER};
+use actix_web::http::Method;
+use actix_web::http::StatusCode;
+use actix_web::{web, Error, HttpResponse};
+use actix_web_lab::middleware::Next;
+use crate::auth::Auth;
+use crate::error::ApiError;
+use crate::peer::PeerCred;
+
+/// Requests one client may make per window to endpoints that change state
+pub const WRITE_LIMIT: u32 = 10;
+/// Requests one client may make per window to read-only endpoints
+pub const READ_LIMIT: u32 = 60;
+pub const WINDOW: Duration = Duration::from_secs(60);
+
+struct Windows {
+    requests: HashMap<String, (u32, Instant)>,
+    last_sweep: Instant,
+}
+
+/// Fixed-window request counter per client
+pub struct RateLimiter {
+    state: Mutex<Windows>,
+    limit: u32,
+    window: Duration,
+}
+
+/// Outcome of one `RateLimiter::check`, reported back in the `X-RateLimit-*` headers
+pub struct Decision {
+    pub allowed: bool,
+    pub limit: u32,
+    pub remaining: u32,
+    /// Until the client's window starts over
+    pub reset: Duration,
+}
+
+impl RateLimiter {
+    pub fn new(limit: u32, window: Duration) -> Self {
+        Self {
+            state: Mutex::new(Windows { requests: HashMap::new(), last_sweep: Instant::now() }),
+            limit,
+            window,
+        }
+    }
+
+    /// Count a request from `client`, unless it has used up its window
+    pub fn check(&self, client: String) -> Decision {
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        // Once per window, forget clients whose window has run out
+        if now.duration_since(state.last_sweep) >= self.window {
+            let window = self.window;
+            state.requests.retain(|_, (_, start)| now.duration_since(*start) < window);
+            state.last_sweep = now;
+        }
+        let entry = state.requests.entry(client).or_insert((0, now));
+        if now.duration_since(entry.1) >= self.window {
+            *entry = (0, now);
+        }
+        let allowed = entry.0 < self.limit;
+        if allowed {
+            entry.0 += 1;
+        }
+        Decision {
+            allowed,
+            limit: self.limit,
+            remaining: self.limit - entry.0,
+            reset: self.window.saturating_sub(now.duration_since(entry.1)),
+        }
+    }
+}
+
+/// Separate budgets so polling dashboards can't starve reservations, or vice versa
+pub struct RateLimits {
+    pub read: RateLimiter,
+    pub write: RateLimiter,
+}
+
+impl Default for RateLimits {
+    fn default() -> Self {
+        RateLimits::new(READ_LIMIT, WRITE_LIMIT, WINDOW)
+    }
+}
+
+impl RateLimits {
+    pub fn new(read: u32, write: u32, window: Duration) -> Self {
+        RateLimits { read: RateLimiter::new(read, window), write: RateLimiter::new(write, window) }
+    }
+}
+
+/// Requests with a valid token count against the token's user, wherever they come
+/// from; Unix socket callers without one count against their UID, and anything else
+/// against the peer address. `X-Forwarded-For` is ignored since any client can set it.
+fn client_key(req: &ServiceRequest) -> String {
+    let user = req
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .zip(req.app_data::<web::Data<Auth>>())
+        .and_then(|(token, auth)| auth.verify(token).ok());
+    match (user, req.conn_data::<PeerCred>()) {
+        (Some(claims), _) => format!("user:{}", claims.sub),
+        (None, Some(peer)) => peer.username(),
+        (None, None) => format!("ip:{}", req.peer_addr().map(|a| a.ip().to_string()).unwrap_or_else(|| "unknown".into())),
+    }
+}
+
+pub async fn rate_limit_middleware<B: MessageBody>(
+    req: ServiceRequest,
+    next: Next<B>,
+) -> Result<ServiceResponse<EitherBody<B>>, Error> {
+    let limits = match req.app_data::<web::Data<RateLimits>>() {
+        Some(limits) => limits.clone(),
+        None => return next.call(req).await.map(ServiceResponse::map_into_left_body),
+    };
+    let limiter = match *req.method() {
+        Method::GET | Method::HEAD => &limits.read,
+        _ => &limits.write,
+    };
+    let decision = limiter.check(client_key(&req));
+    let reset_secs = (decision.reset.as_millis() as u64).div_ceil(1000);
+    let mut res = if decision.allowed {
+        next.call(req).await?.map_into_left_body()
+    } else {
+        let error = ApiError::new(StatusCode::TOO_MANY_REQUESTS, "RATE_LIMITED", "Rate limit exceeded.");
+        let body = HttpResponse::TooManyRequests().insert_header((RETRY_AFTER, reset_secs)).json(error);
+        req.into_response(body).map_into_right_body()
+    };
+    let headers = res.headers_mut();
+    headers.insert(HeaderName::from_static("x-ratelimit-limit"), HeaderValue::from(decision.limit));
+    headers.insert(HeaderName::from_static("x-ratelimit-remaining"), HeaderValue::from(decision.remaining));
+    headers.insert(HeaderName::from_static("x-ratelimit-reset"), HeaderValue::from(reset_secs));
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::{call_service, init_service, TestRequest};
+    use actix_web::App;
+    use actix_web_lab::middleware::from_fn;
+
+    #[test]
+    fn counts_each_client_in_its_own_window() {
+        let limiter = RateLimiter::new(2, WINDOW);
+        assert!(limiter.check("ip:a".into()).allowed);
+        let decision = limiter.check("ip:a".into());
+        assert!(decision.allowed);
+        assert_eq!((decision.limit, decision.remaining), (2, 0));
+        assert!(!limiter.check("ip:a".into()).allowed);
+        assert!(limiter.check("ip:b".into()).allowed);
+    }
+
+    #[test]
+    fn starts_over_and_sweeps_idle_clients_after_the_window() {
+        let window = Duration::from_millis(50);
+        let limiter = RateLimiter::new(1, window);
+        assert!(limiter.check("ip:idle".into()).allowed);
+        assert!(limiter.check("ip:busy".into()).allowed);
+        assert!(!limiter.check("ip:busy".into()).allowed);
+        std::thread::sleep(window * 2);
+        assert!(limiter.check("ip:busy".into()).allowed);
+        let state = limiter.state.lock().unwrap();
+        assert!(!state.requests.contains_key("ip:idle"));
+        assert_eq!(state.requests.len(), 1);
+    }
+
+    #[actix_web::test]
+    async fn reads_and_writes_have_separate_budgets() {
+        let limits = web::Data::new(RateLimits::new(2, 1, WINDOW));
+        let app = init_service(
+            App::new()
+                .app_data(limits)
+                .wrap(from_fn(rate_limit_middleware))
+                .route("/", web::get().to(HttpResponse::Ok))
+                .route("/", web::post().to(HttpResponse::Ok)),
+        )
+        .await;
+        let peer = "192.0.2.1:40000".parse().unwrap();
+        let get = || TestRequest::get().uri("/").peer_addr(peer).to_request();
+        let post = || TestRequest::post().uri("/").peer_addr(peer).to_request();
+
+        let res = call_service(&app, post()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers().get("x-ratelimit-remaining").unwrap(), "0");
+        // The write budget is spent, but reads still have theirs
+        assert_eq!(call_service(&app, post()).await.status(), StatusCode::TOO_MANY_REQUESTS);
+        let res = call_service(&app, get()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers().get("x-ratelimit-limit").unwrap(), "2");
+        assert_eq!(call_service(&app, get()).await.status(), StatusCode::OK);
+        let res = call_service(&app, get()).await;
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert!(res.headers().contains_key(RETRY_AFTER));
+        // Another address has budgets of its own
+        let other = TestRequest::post().uri("/").peer_addr("192.0.2.2:40000".parse().unwrap()).to_request();
+        assert_eq!(call_service(&app, other).await.status(), StatusCode::OK);
+    }
+}
@@ -0,0 +1,156 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use actix_web::{http::StatusCode, HttpResponse, ResponseError};
+use port_manager_core::config_dir;
+use serde::{Deserialize, Serialize};
+
+/// Something a role may be allowed to do, written in snake case in `roles.toml`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, utoipa::ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Permission {
+    /// See ports, pools and reservation status
+    ListPorts,
+    /// Reserve, allocate, renew and release ports the caller owns
+    Reserve,
+    /// Release or renew reservations held by anyone
+    ReleaseAny,
+    /// Signal processes and free ports by terminating their owners
+    Kill,
+    /// Read the audit log
+    ViewAudit,
+}
+
+/// Which permissions each role grants. A role missing from the map grants nothing.
+#[derive(Debug, Clone)]
+pub struct Roles(HashMap<String, HashSet<Permission>>);
+
+impl Default for Roles {
+    fn default() -> Self {
+        use Permission::*;
+        Roles(HashMap::from([
+            ("viewer".to_string(), HashSet::from([ListPorts])),
+            ("developer".to_string(), HashSet::from([ListPorts, Reserve])),
+            ("admin".to_string(), HashSet::from([ListPorts, Reserve, ReleaseAny, Kill, ViewAudit])),
+        ]))
+    }
+}
+
+impl Roles {
+    pub fn permissions(&self, role: &str) -> HashSet<Permission> {
+        self.0.get(role).cloned().unwrap_or_default()
+    }
+
+    pub fn contains(&self, role: &str) -> bool {
+        self.0.contains_key(role)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RolesFile {
+    #[serde(default)]
+    roles: HashMap<String, HashSet<Permission>>,
+}
+
+/// Role definitions used by the API unless told otherwise
+pub fn default_roles_path() -> PathBuf {
+    config_dir().join("roles.toml")
+}
+
+/// Read role overrides from a TOML file:
+///
+/// ```toml
+/// [roles]
+/// viewer = ["list_ports"]
+/// ci = ["list_ports", "reserve"]
+/// ```
+///
+/// Each listed role replaces the built-in one of the same name; `viewer`, `developer`
+/// and `admin` keep their defaults otherwise. A missing file means the defaults; one
+/// that doesn't parse, has keys besides `roles` or names an unknown permission is an error.
+pub fn load_roles(path: impl AsRef<Path>) -> Result<Roles, String> {
+    let path = path.as_ref();
+    let mut roles = Roles::default();
+    if !path.exists() {
+        return Ok(roles);
+    }
+    let data = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let file: RolesFile = toml::from_str(&data).map_err(|e| format!("{}: {}", path.display(), e))?;
+    roles.0.extend(file.roles);
+    Ok(roles)
+}
+
+/// A call refused for lack of a permission or because the caller doesn't own the
+/// reservation, answered with a 403 in the `ApiError` envelope plus the role and the
+/// permission it lacked
+#[derive(Debug, Serialize, utoipa::ToSchema)]
+pub struct Forbidden {
+    pub code: &'static str,
+    pub message: String,
+    pub role: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub permission: Option<Permission>,
+}
+
+impl Forbidden {
+    pub fn new(role: &str, permission: Option<Permission>, message: String) -> Self {
+        Forbidden { code: "FORBIDDEN", message, role: role.to_string(), permission }
+    }
+}
+
+impl fmt::Display for Forbidden {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl ResponseError for Forbidden {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::FORBIDDEN
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::Forbidden().json(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(toml: &str) -> Result<Roles, String> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("roles.toml");
+        std::fs::write(&path, toml).unwrap();
+        load_roles(&path)
+    }
+
+    #[test]
+    fn listed_roles_replace_the_defaults() {
+        let roles = load("[roles]\nviewer = []\nci = [\"list_ports\", \"reserve\"]\n").unwrap();
+        assert!(roles.permissions("viewer").is_empty());
+        assert_eq!(roles.permissions("ci"), HashSet::from([Permission::ListPorts, Permission::Reserve]));
+        assert_eq!(roles.permissions("admin"), Roles::default().permissions("admin"));
+        assert!(!roles.contains("nobody"));
+    }
+
+    #[test]
+    fn missing_file_means_the_defaults() {
+        let dir = tempfile::tempdir().unwrap();
+        let roles = load_roles(dir.path().join("roles.toml")).unwrap();
+        assert_eq!(roles.permissions("developer"), Roles::default().permissions("developer"));
+    }
+
+    #[test]
+    fn rejects_malformed_files() {
+        for toml in [
+            "[roles\nviewer = [\"list_ports\"]",
+            "[role]\nviewer = [\"list_ports\"]",
+            "[roles]\nviewer = [\"delete_everything\"]",
+            "[roles]\nviewer = \"list_ports\"",
+        ] {
+            assert!(load(toml).is_err(), "{:?}", toml);
+        }
+    }
+}
@@ -1,4 +1,14 @@
-use crate::reservation::PortReservationManager;
+use crate::daemon::{Daemon, DaemonError};
+use nix::sys::signal::{kill, Signal};
+use nix::unistd::Pid;
+use port_manager_core::{
+    addrs_overlap, default_pools_path, default_store_path, load_pools, local_owner, unix_now, AuditEntry, AuditQuery,
+    PortEvent, PortInfo, PortReservationManager, Protocol, Reservation, ReservationError, ReservationRequest,
+};
+pub use port_manager_core::SortBy;
+use std::net::IpAddr;
+use std::sync::mpsc::{Receiver, TryRecvError};
+use std::time::{Duration, Instant};
 use ratatui::style::Color;
 #[derive(Clone)]
 pub struct Theme {
@@ -31,16 +41,7 @@ impl Theme {
     }
 }
 
-#[derive(Clone, Copy)]
-pub enum SortBy {
-    Port,
-    Process,
-    Protocol,
-    State,
-}
-
 use ratatui::widgets::TableState;
-use crate::net::PortInfo;
 
 pub struct App {
     pub search: String,
@@ -54,11 +55,50 @@ pub struct App {
     pub reservation_manager: PortReservationManager,
     pub reservation_popup: Option<(u16, String)>, // (port, service)
     pub reservation_error: Option<String>,
+    /// Who the audit log says did what from this TUI
+    pub actor: String,
+    pub show_audit: bool,
+    pub audit_entries: Vec<AuditEntry>,
+    /// Live reservations, from the daemon or the local store, shown next to each socket
+    pub reservations: Vec<Reservation>,
+    /// The API daemon reservations go through, if one was found or named
+    pub daemon: Option<Daemon>,
+    /// Reservation events from the daemon; `None` while working on the local store
+    events: Option<Receiver<PortEvent>>,
+    last_sync: Instant,
+    audit_refreshed: Option<Instant>,
 }
 
+/// Audit entries the panel shows
+const AUDIT_PANEL_ROWS: u32 = 200;
+/// How often the daemon's full reservation list is fetched again, on top of its events,
+/// and how often an unreachable daemon is retried
+const DAEMON_RESYNC: Duration = Duration::from_secs(30);
+/// How often the audit panel asks the daemon for new entries, to stay well inside its rate limit
+const DAEMON_AUDIT_REFRESH: Duration = Duration::from_secs(5);
+
 impl App {
     pub fn new() -> Self {
-        Self {
+        // Fall back to an in-memory store (and say so) if the shared database can't be opened
+        let (reservation_manager, mut reservation_error) = match PortReservationManager::open(default_store_path()) {
+            Ok(manager) => (manager, None),
+            Err(e) => (PortReservationManager::new(), Some(format!("Reservations will not be saved: {}", e))),
+        };
+        let reservation_manager = match load_pools(default_pools_path()) {
+            Ok(pools) => reservation_manager.with_pools(pools),
+            Err(e) => {
+                reservation_error = Some(format!("Ignoring pools: {}", e));
+                reservation_manager
+            }
+        };
+        let daemon = match Daemon::from_env() {
+            Ok(daemon) => daemon,
+            Err(e) => {
+                reservation_error = Some(format!("Ignoring PORTMGR_DAEMON: {}", e));
+                None
+            }
+        };
+        let mut app = Self {
             search: String::new(),
             search_mode: false,
             ports: Vec::new(),
@@ -67,28 +107,159 @@ impl App {
             confirm_kill: None,
             theme: Theme::default(),
             sort_by: SortBy::Port,
-            reservation_manager: PortReservationManager::new(),
+            reservation_manager,
             reservation_popup: None,
-            reservation_error: None,
+            reservation_error,
+            actor: std::env::var("USER").unwrap_or_else(|_| "tui".to_string()),
+            show_audit: false,
+            audit_entries: Vec::new(),
+            reservations: Vec::new(),
+            daemon,
+            events: None,
+            last_sync: Instant::now(),
+            audit_refreshed: None,
+        };
+        app.connect_daemon();
+        app.sync_reservations();
+        app
+    }
+
+    /// The daemon, while it is reachable; otherwise reservations use the local store
+    pub fn remote(&self) -> Option<&Daemon> {
+        self.daemon.as_ref().filter(|_| self.events.is_some())
+    }
+
+    /// Subscribe to the daemon's events and take its reservations, or stay local and say why
+    fn connect_daemon(&mut self) {
+        self.last_sync = Instant::now();
+        let Some(daemon) = &self.daemon else { return };
+        let connected = daemon.subscribe().and_then(|events| Ok((events, daemon.reservations()?)));
+        match connected {
+            Ok((events, reservations)) => {
+                self.events = Some(events);
+                self.reservations = reservations;
+            }
+            Err(e) => {
+                let hint = match &e {
+                    DaemonError::Api { code, .. } if code == "UNAUTHORIZED" => " Set PORTMGR_TOKEN to a token from /login.",
+                    _ => "",
+                };
+                self.reservation_error = Some(format!("{} ({}); using the local store.{}", e, daemon.endpoint, hint));
+            }
+        }
+    }
+
+    /// The daemon went away: carry on locally until it comes back
+    fn disconnect(&mut self, reason: String) {
+        self.events = None;
+        if let Some(daemon) = &self.daemon {
+            self.reservation_error = Some(format!("{} ({}); using the local store.", reason, daemon.endpoint));
+        }
+        self.reservations = self.reservation_manager.get_all_reservations();
+    }
+
+    /// Bring `reservations` up to date: apply the daemon's events, or re-read the local store
+    pub fn sync_reservations(&mut self) {
+        let Some(events) = &self.events else {
+            if self.daemon.is_some() && self.last_sync.elapsed() >= DAEMON_RESYNC {
+                self.connect_daemon();
+            }
+            if self.events.is_none() {
+                self.reservations = self.reservation_manager.get_all_reservations();
+            }
+            return;
+        };
+        let mut received = Vec::new();
+        let closed = loop {
+            match events.try_recv() {
+                Ok(event) => received.push(event),
+                Err(TryRecvError::Empty) => break false,
+                Err(TryRecvError::Disconnected) => break true,
+            }
+        };
+        for event in received {
+            match event {
+                PortEvent::Reserved { reservation } => self.remember(reservation),
+                PortEvent::Released { reservation } => self.reservations.retain(|r| r.key() != reservation.key()),
+                _ => {}
+            }
+        }
+        if closed {
+            self.disconnect("Lost the daemon".to_string());
+        } else if self.last_sync.elapsed() >= DAEMON_RESYNC {
+            // Events don't cover renewals, so pick those up now and then
+            self.last_sync = Instant::now();
+            match self.remote().map(Daemon::reservations) {
+                Some(Ok(reservations)) => self.reservations = reservations,
+                Some(Err(DaemonError::Unreachable(e))) => self.disconnect(format!("Daemon unreachable: {}", e)),
+                _ => {}
+            }
+        }
+    }
+
+    fn remember(&mut self, reservation: Reservation) {
+        self.reservations.retain(|r| r.key() != reservation.key());
+        self.reservations.push(reservation);
+    }
+
+    /// The reservation a socket on `protocol`/`port`/`addr` falls under, if any
+    pub fn find_reservation(&self, protocol: Protocol, port: u16, addr: Option<IpAddr>) -> Option<&Reservation> {
+        let now = unix_now();
+        self.reservations.iter().find(|r| {
+            r.protocol == protocol
+                && r.port == port
+                && addrs_overlap(r.bind_addr, addr)
+                && r.expires_at.is_none_or(|at| at > now)
+        })
+    }
+
+    /// Append to the audit log; a store that can't take it shows up as an error
+    fn audit(&mut self, entry: AuditEntry) {
+        if let Err(e) = self.reservation_manager.record_audit(&entry) {
+            self.reservation_error = Some(format!("Audit log: {}", e));
+        }
+    }
+
+    /// Reload the audit panel: the daemon's log when connected, the local one otherwise
+    pub fn refresh_audit(&mut self) {
+        let result = match self.remote() {
+            Some(_) if self.audit_refreshed.is_some_and(|at| at.elapsed() < DAEMON_AUDIT_REFRESH) => return,
+            Some(daemon) => daemon.audit(AUDIT_PANEL_ROWS).map_err(|e| e.to_string()),
+            None => {
+                let query = AuditQuery { limit: Some(AUDIT_PANEL_ROWS), ..AuditQuery::default() };
+                self.reservation_manager.audit_log(&query).map_err(|e| e.to_string())
+            }
+        };
+        self.audit_refreshed = Some(Instant::now());
+        match result {
+            Ok(entries) => self.audit_entries = entries,
+            Err(e) => {
+                // Don't keep asking for a log we may not read
+                self.show_audit = false;
+                self.reservation_error = Some(format!("Audit log: {}", e));
+            }
+        }
+    }
+
+    pub fn toggle_audit(&mut self) {
+        self.show_audit = !self.show_audit;
+        if self.show_audit {
+            self.audit_refreshed = None;
+            self.refresh_audit();
         }
     }
 
     pub fn update_filtered_ports(&mut self) {
         self.filtered_ports = self.ports
             .iter()
-            .cloned()
             .filter(|p| p.matches(&self.search))
+            .cloned()
             .collect();
         self.sort_ports();
     }
 
     pub fn sort_ports(&mut self) {
-        match self.sort_by {
-            SortBy::Port => self.filtered_ports.sort_by_key(|p| p.local_addr.clone()),
-            SortBy::Process => self.filtered_ports.sort_by_key(|p| p.process.clone().unwrap_or_default()),
-            SortBy::Protocol => self.filtered_ports.sort_by_key(|p| p.state.clone()),
-            SortBy::State => self.filtered_ports.sort_by_key(|p| p.state.clone()),
-        }
+        self.sort_by.sort(&mut self.filtered_ports);
     }
 
     pub fn selected_index(&self) -> usize {
@@ -117,18 +288,88 @@ impl App {
         self.filtered_ports.get(self.selected_index())
     }
 
+    /// Show the outcome of a request to the daemon, falling back to local mode if it's gone.
+    /// The daemon keeps its own audit log.
+    fn daemon_result(&mut self, result: Result<Reservation, DaemonError>, released: bool) {
+        match result {
+            Ok(r) => {
+                let port = r.port;
+                let popup = if released {
+                    self.reservations.retain(|kept| kept.key() != r.key());
+                    "released".to_string()
+                } else {
+                    let service = r.service.clone();
+                    self.remember(r);
+                    service
+                };
+                self.reservation_popup = Some((port, popup));
+            }
+            Err(DaemonError::Unreachable(e)) => self.disconnect(format!("Daemon unreachable: {}", e)),
+            Err(e) => self.reservation_error = Some(e.to_string()),
+        }
+        self.audit_refreshed = None;
+    }
+
     // Helper to reserve port from UI
-    pub fn try_reserve_port(&mut self, port: u16, service: String) {
-        match self.reservation_manager.reserve_port(port, service.clone()) {
-            Ok(_) => self.reservation_popup = Some((port, service)),
-            Err(e) => self.reservation_error = Some(e),
+    pub fn try_reserve_port(&mut self, protocol: Protocol, port: u16, service: String) {
+        // A daemon records the caller itself, so the owner only counts locally
+        let owner = Some(local_owner());
+        let req = ReservationRequest { protocol, owner, ..ReservationRequest::new(service.clone()) };
+        if let Some(daemon) = self.remote() {
+            let result = daemon.reserve(port, &req);
+            return self.daemon_result(result, false);
+        }
+        let entry = AuditEntry::new(&self.actor, "reserve").port(protocol, port).detail(service.clone());
+        match self.reservation_manager.reserve(port, req) {
+            Ok(_) => {
+                self.audit(entry);
+                self.reservation_popup = Some((port, service));
+            }
+            Err(e) => {
+                self.audit(entry.failed(e.code(), e.message()));
+                self.reservation_error = Some(e.to_string());
+            }
+        }
+    }
+
+    /// Release whichever reservation covers a socket on `protocol`/`port`/`addr`
+    pub fn try_release_port(&mut self, protocol: Protocol, port: u16, addr: Option<IpAddr>) {
+        if let Some(daemon) = self.remote() {
+            let result = match self.find_reservation(protocol, port, addr) {
+                Some(r) => daemon.release(r.key()),
+                None => {
+                    self.reservation_error = Some(format!("Port {} was not reserved.", port));
+                    return;
+                }
+            };
+            return self.daemon_result(result, true);
+        }
+        let result = match self.reservation_manager.find_reservation(protocol, port, addr) {
+            Some(r) => self.reservation_manager.release(r.key()),
+            None => Err(ReservationError::NotFound(format!("Port {} was not reserved.", port))),
+        };
+        let entry = AuditEntry::new(&self.actor, "release").port(protocol, port);
+        match result {
+            Ok(r) => {
+                self.audit(entry.detail(r.service));
+                self.reservation_popup = Some((port, "released".to_string()));
+            }
+            Err(e) => {
+                self.audit(entry.failed(e.code(), e.message()));
+                self.reservation_error = Some(e.to_string());
+            }
         }
     }
 
-    pub fn try_release_port(&mut self, port: u16) {
-        match self.reservation_manager.release_port(port) {
-            Ok(_) => self.reservation_popup = Some((port, "released".to_string())),
-            Err(e) => self.reservation_error = Some(e),
+    /// SIGTERM `pid`, as confirmed in the kill prompt
+    pub fn kill_process(&mut self, pid: i32) {
+        let entry = AuditEntry::new(&self.actor, "signal").pid(pid).detail("SIGTERM");
+        match kill(Pid::from_raw(pid), Signal::SIGTERM) {
+            Ok(()) => self.audit(entry),
+            Err(errno) => {
+                self.audit(entry.failed(format!("{:?}", errno), errno.desc()));
+                self.reservation_error = Some(format!("Could not kill PID {}: {}", pid, errno.desc()));
+            }
         }
     }
 }
@@ -0,0 +1,813 @@
+//! Non-interactive subcommands for scripts and Makefiles. Every command exits with one
+//! of the `EXIT_*` codes and, given `--json`, prints JSON on stdout.
+
+use crate::daemon::{Daemon, DaemonError};
+use nix::errno::Errno;
+use nix::sys::signal::{kill, sigaction, SaFlags, SigAction, SigHandler, SigSet, Signal};
+use nix::unistd::Pid;
+use port_manager_core::{
+    addrs_overlap, default_pools_path, default_store_path, is_port_in_use, is_protected, list_ports, load_pools,
+    local_owner, port_owners, AuditEntry, PortInfo, PortRange, PortReservationManager, Protocol, Reservation,
+    ReservationError, ReservationKey, ReservationRequest, SortBy,
+};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::os::unix::process::ExitStatusExt;
+use std::process::{Command, ExitStatus};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::time::{Duration, Instant};
+
+pub const EXIT_OK: i32 = 0;
+/// Anything not covered below, such as a broken database or an internal daemon failure
+pub const EXIT_ERROR: i32 = 1;
+/// Bad arguments, or a request the store refuses as invalid
+pub const EXIT_USAGE: i32 = 2;
+/// Nothing holds, reserves or listens on the port
+pub const EXIT_NOT_FOUND: i32 = 3;
+/// The port is taken: reserved, bound, or no port in the range is left
+pub const EXIT_CONFLICT: i32 = 4;
+/// Not allowed: another user's reservation or process, or a protected one
+pub const EXIT_DENIED: i32 = 5;
+/// `wait-free` gave up
+pub const EXIT_TIMEOUT: i32 = 6;
+/// The daemon stopped answering, or its reply made no sense
+pub const EXIT_UNREACHABLE: i32 = 7;
+/// The daemon's rate limit refused the request; retry later
+pub const EXIT_RATE_LIMITED: i32 = 8;
+
+pub const USAGE: &str = "usage: port-manager [<command>] [--json]
+
+Without a command, starts the interactive TUI.
+
+commands:
+  list [--listen] [--proto tcp|udp]          sockets, with their reservations
+  who <port> [--proto tcp|udp]               what holds and reserves a port
+  reserve <port> <service> [--proto P] [--ttl SECS] [--pool NAME]
+  release <port> [--proto P]
+  allocate [service] (--range 3000-3999 | --pool NAME) [--proto P] [--ttl SECS]
+  kill <port> [--proto P] [--signal TERM]    signal whatever holds a port
+  wait-free <port> [--proto P] [--timeout SECS]
+  run (--pool NAME | --range 3000-3999) [--service NAME] [--env PORT] [--ttl SECS]
+      [--proto P] [--bind-timeout SECS] -- <command> [args...]
+                                             run a command on a fresh port, then release it
+
+exit codes: 0 ok, 1 error, 2 usage, 3 not found, 4 taken, 5 denied, 6 timed out,
+7 daemon unreachable, 8 rate limited; run exits with its command's status";
+
+/// Wait between checks in `wait-free`
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+/// `wait-free` gives up after this long unless told otherwise; `--timeout 0` waits forever
+const DEFAULT_WAIT: Duration = Duration::from_secs(30);
+/// How long `run` gives its command to bind the port before warning
+const DEFAULT_BIND_WAIT: Duration = Duration::from_secs(30);
+/// How often `run` looks for the command's socket
+const BIND_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+/// Stands for the allocated port in the arguments of `run`
+const PORT_PLACEHOLDER: &str = "{port}";
+
+/// Why a command failed, with the same codes the API uses
+#[derive(Debug, Serialize)]
+pub struct Failure {
+    code: String,
+    message: String,
+}
+
+impl Failure {
+    fn new(code: &str, message: impl Into<String>) -> Self {
+        Failure { code: code.to_string(), message: message.into() }
+    }
+
+    fn usage(message: impl Into<String>) -> Self {
+        Failure::new("USAGE", message)
+    }
+
+    fn exit_code(&self) -> i32 {
+        match self.code.as_str() {
+            "USAGE" | "INVALID_REQUEST" => EXIT_USAGE,
+            "NOT_FOUND" => EXIT_NOT_FOUND,
+            "ALREADY_RESERVED" | "IN_USE" | "NO_FREE_PORT" | "LIMIT_REACHED" => EXIT_CONFLICT,
+            "FORBIDDEN" | "UNAUTHORIZED" | "PROTECTED_PROCESS" => EXIT_DENIED,
+            "TIMEOUT" => EXIT_TIMEOUT,
+            "UNREACHABLE" => EXIT_UNREACHABLE,
+            "RATE_LIMITED" => EXIT_RATE_LIMITED,
+            // STORAGE_ERROR, INTERNAL, and HTTP_<status> for replies without an error body
+            _ => EXIT_ERROR,
+        }
+    }
+}
+
+impl From<ReservationError> for Failure {
+    fn from(e: ReservationError) -> Self {
+        Failure::new(e.code(), e.message())
+    }
+}
+
+impl From<DaemonError> for Failure {
+    fn from(e: DaemonError) -> Self {
+        match e {
+            DaemonError::Api { code, message } => Failure { code, message },
+            DaemonError::Unreachable(_) => Failure::new("UNREACHABLE", e.to_string()),
+        }
+    }
+}
+
+/// Flags of one command: switches take no value, options take one
+struct Args {
+    positional: Vec<String>,
+    flags: HashMap<String, Option<String>>,
+}
+
+impl Args {
+    fn parse(args: &[String], switches: &[&str], options: &[&str]) -> Result<Self, Failure> {
+        let mut parsed = Args { positional: Vec::new(), flags: HashMap::new() };
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            if arg == "--" {
+                // Everything after `--` belongs to the command `run` starts
+                parsed.positional.extend(args.cloned());
+                break;
+            }
+            let Some(flag) = arg.strip_prefix("--") else {
+                parsed.positional.push(arg.clone());
+                continue;
+            };
+            let (name, inline) = match flag.split_once('=') {
+                Some((name, value)) => (name, Some(value.to_string())),
+                None => (flag, None),
+            };
+            if switches.contains(&name) && inline.is_none() {
+                parsed.flags.insert(name.to_string(), None);
+            } else if options.contains(&name) {
+                let value = inline
+                    .or_else(|| args.next().cloned())
+                    .ok_or_else(|| Failure::usage(format!("--{} needs a value", name)))?;
+                parsed.flags.insert(name.to_string(), Some(value));
+            } else {
+                return Err(Failure::usage(format!("Unknown flag --{}", name)));
+            }
+        }
+        Ok(parsed)
+    }
+
+    fn has(&self, name: &str) -> bool {
+        self.flags.contains_key(name)
+    }
+
+    fn get(&self, name: &str) -> Option<&str> {
+        self.flags.get(name).and_then(|v| v.as_deref())
+    }
+
+    /// Parse option `name` if given
+    fn value<T: FromStr>(&self, name: &str) -> Result<Option<T>, Failure>
+    where
+        T::Err: std::fmt::Display,
+    {
+        self.get(name)
+            .map(|v| v.parse().map_err(|e| Failure::usage(format!("--{}: {}", name, e))))
+            .transpose()
+    }
+
+    fn protocol(&self) -> Result<Option<Protocol>, Failure> {
+        self.value("proto")
+    }
+
+    /// The positional argument at `index`, parsed
+    fn arg<T: FromStr>(&self, index: usize, what: &str) -> Result<T, Failure> {
+        let value = self.positional.get(index).ok_or_else(|| Failure::usage(format!("Missing <{}>", what)))?;
+        value.parse().map_err(|_| Failure::usage(format!("Invalid {} '{}'", what, value)))
+    }
+
+    fn ttl(&self) -> Result<Option<Duration>, Failure> {
+        Ok(self.value::<u64>("ttl")?.map(Duration::from_secs))
+    }
+}
+
+/// Who the local audit log says did what from the command line
+fn actor() -> String {
+    std::env::var("USER").unwrap_or_else(|_| "cli".to_string())
+}
+
+fn local_manager() -> Result<PortReservationManager, Failure> {
+    let manager = PortReservationManager::open(default_store_path()).map_err(|e| Failure::new("STORAGE_ERROR", e))?;
+    let pools = load_pools(default_pools_path()).map_err(|e| Failure::new("INVALID_REQUEST", e))?;
+    Ok(manager.with_pools(pools))
+}
+
+/// Record a local action; a log that can't take it is only worth a warning
+fn audit(manager: &PortReservationManager, entry: AuditEntry) {
+    if let Err(e) = manager.record_audit(&entry) {
+        eprintln!("port-manager: audit log: {}", e);
+    }
+}
+
+/// Where reservations live: a reachable daemon, like the TUI uses, or the local database
+enum Store {
+    Daemon(Daemon),
+    Local(PortReservationManager),
+}
+
+impl Store {
+    fn open() -> Result<Self, Failure> {
+        match Daemon::from_env().map_err(Failure::usage)? {
+            Some(daemon) if daemon.reachable() => Ok(Store::Daemon(daemon)),
+            Some(daemon) => {
+                eprintln!("port-manager: no daemon at {}; using the local store", daemon.endpoint);
+                Ok(Store::Local(local_manager()?))
+            }
+            None => Ok(Store::Local(local_manager()?)),
+        }
+    }
+
+    fn reservations(&self) -> Result<Vec<Reservation>, Failure> {
+        match self {
+            Store::Daemon(daemon) => Ok(daemon.reservations()?),
+            Store::Local(manager) => Ok(manager.get_all_reservations()),
+        }
+    }
+
+    fn reserve(&self, port: u16, req: ReservationRequest) -> Result<Reservation, Failure> {
+        match self {
+            Store::Daemon(daemon) => Ok(daemon.reserve(port, &req)?),
+            Store::Local(manager) => {
+                let entry = AuditEntry::new(actor(), "reserve").port(req.protocol, port).detail(req.service.clone());
+                let req = ReservationRequest { owner: Some(local_owner()), ..req };
+                record(manager, entry, manager.reserve(port, req))
+            }
+        }
+    }
+
+    fn allocate(&self, range: Option<PortRange>, req: ReservationRequest) -> Result<Reservation, Failure> {
+        match self {
+            Store::Daemon(daemon) => Ok(daemon.allocate(range, &req)?),
+            Store::Local(manager) => {
+                let protocol = req.protocol;
+                let result = manager.allocate(range, ReservationRequest { owner: Some(local_owner()), ..req });
+                let entry = AuditEntry::new(actor(), "allocate");
+                let entry = match &result {
+                    Ok(r) => entry.port(protocol, r.port).detail(r.service.clone()),
+                    Err(_) => entry,
+                };
+                record(manager, entry, result)
+            }
+        }
+    }
+
+    /// Extend a lease by its own TTL
+    fn renew(&self, key: ReservationKey) -> Result<Reservation, Failure> {
+        match self {
+            Store::Daemon(daemon) => Ok(daemon.renew(key)?),
+            Store::Local(manager) => Ok(manager.renew_lease(key, None)?),
+        }
+    }
+
+    /// Release `held` only if nobody has taken its key over since
+    fn release_exact(&self, held: &Reservation) -> Result<Reservation, Failure> {
+        match self {
+            Store::Daemon(daemon) => Ok(daemon.release_exact(held)?),
+            Store::Local(manager) => {
+                let entry = AuditEntry::new(actor(), "release").port(held.protocol, held.port).detail(held.service.clone());
+                record(manager, entry, manager.release_exact(held))
+            }
+        }
+    }
+
+    /// Release the reservation covering `protocol`/`port` on any address
+    fn release(&self, protocol: Protocol, port: u16) -> Result<Reservation, Failure> {
+        let not_found = || Failure::new("NOT_FOUND", format!("{} port {} is not reserved.", protocol, port));
+        match self {
+            Store::Daemon(daemon) => {
+                let reservations = daemon.reservations()?;
+                let r = reservations.iter().find(|r| r.protocol == protocol && r.port == port).ok_or_else(not_found)?;
+                Ok(daemon.release(r.key())?)
+            }
+            Store::Local(manager) => {
+                let entry = AuditEntry::new(actor(), "release").port(protocol, port);
+                let result = match manager.find_reservation(protocol, port, None) {
+                    Some(r) => manager.release(r.key()),
+                    None => Err(ReservationError::NotFound(not_found().message)),
+                };
+                let entry = match &result {
+                    Ok(r) => entry.detail(r.service.clone()),
+                    Err(_) => entry,
+                };
+                record(manager, entry, result)
+            }
+        }
+    }
+}
+
+/// Audit a local reservation change, successful or not
+fn record(
+    manager: &PortReservationManager,
+    entry: AuditEntry,
+    result: Result<Reservation, ReservationError>,
+) -> Result<Reservation, Failure> {
+    match &result {
+        Ok(_) => audit(manager, entry),
+        Err(e) => audit(manager, entry.failed(e.code(), e.message())),
+    }
+    Ok(result?)
+}
+
+/// Print `value` as JSON, or `text` for people
+fn output<T: Serialize>(json: bool, value: &T, text: impl FnOnce() -> String) {
+    if json {
+        match serde_json::to_string_pretty(value) {
+            Ok(s) => println!("{}", s),
+            Err(e) => eprintln!("port-manager: {}", e),
+        }
+    } else {
+        let text = text();
+        if !text.is_empty() {
+            println!("{}", text);
+        }
+    }
+}
+
+/// Run the subcommand in `args` and return the process exit code
+pub fn run(args: &[String]) -> i32 {
+    let json = args.iter().take_while(|a| *a != "--").any(|a| a == "--json");
+    let (command, rest) = match args.split_first() {
+        Some((command, rest)) => (command.as_str(), rest),
+        None => return EXIT_USAGE,
+    };
+    let result = match command {
+        "list" => list(rest),
+        "who" => who(rest),
+        "reserve" => reserve(rest),
+        "release" => release(rest),
+        "allocate" => allocate(rest),
+        "kill" => kill_port(rest),
+        "wait-free" => wait_free(rest),
+        "run" => run_command(rest),
+        "help" | "--help" | "-h" => {
+            println!("{}", USAGE);
+            return EXIT_OK;
+        }
+        _ => Err(Failure::usage(format!("Unknown command '{}'", command))),
+    };
+    match result {
+        Ok(code) => code,
+        Err(failure) => {
+            if json {
+                output(true, &failure, String::new);
+            } else {
+                eprintln!("port-manager: {}", failure.message);
+                if failure.code == "USAGE" {
+                    eprintln!("Run 'port-manager help' for usage.");
+                }
+            }
+            failure.exit_code()
+        }
+    }
+}
+
+/// A socket with the reservation covering it, if any
+#[derive(Serialize)]
+struct ListedPort<'a> {
+    #[serde(flatten)]
+    socket: &'a PortInfo,
+    reservation: Option<&'a Reservation>,
+}
+
+fn covering<'a>(reservations: &'a [Reservation], socket: &PortInfo) -> Option<&'a Reservation> {
+    reservations
+        .iter()
+        .find(|r| r.protocol == socket.protocol && r.port == socket.port && addrs_overlap(r.bind_addr, socket.local_ip()))
+}
+
+fn describe(r: &Reservation) -> String {
+    let mut text = format!("reserved for '{}'", r.service);
+    if let Some(owner) = &r.owner {
+        text.push_str(&format!(" by {}", owner));
+    }
+    if let Some(left) = r.remaining() {
+        text.push_str(&format!(", {}s left", left.as_secs()));
+    }
+    text
+}
+
+fn list(args: &[String]) -> Result<i32, Failure> {
+    let args = Args::parse(args, &["json", "listen"], &["proto"])?;
+    let protocol = args.protocol()?;
+    let mut ports: Vec<PortInfo> = list_ports()
+        .into_iter()
+        .filter(|p| protocol.is_none_or(|proto| p.protocol == proto))
+        // UDP has no listening state; a bound UDP socket is as good as listening
+        .filter(|p| !args.has("listen") || p.state == "Listen" || p.protocol == Protocol::Udp)
+        .collect();
+    SortBy::Port.sort(&mut ports);
+    let reservations = Store::open()?.reservations()?;
+    let listed: Vec<ListedPort> =
+        ports.iter().map(|socket| ListedPort { socket, reservation: covering(&reservations, socket) }).collect();
+    output(args.has("json"), &listed, || {
+        let mut lines = vec![format!("{:<5} {:<40} {:<12} {:>7}  {:<20} {}", "PROTO", "LOCAL", "STATE", "PID", "PROCESS", "RESERVATION")];
+        for p in &listed {
+            lines.push(format!(
+                "{:<5} {:<40} {:<12} {:>7}  {:<20} {}",
+                p.socket.protocol.to_string(),
+                p.socket.local_addr,
+                p.socket.state,
+                p.socket.pid.map_or("-".to_string(), |pid| pid.to_string()),
+                p.socket.process.as_deref().unwrap_or("-"),
+                p.reservation.map(describe).unwrap_or_default(),
+            ));
+        }
+        lines.join("\n")
+    });
+    Ok(EXIT_OK)
+}
+
+#[derive(Serialize)]
+struct Who {
+    port: u16,
+    sockets: Vec<PortInfo>,
+    reservations: Vec<Reservation>,
+}
+
+fn who(args: &[String]) -> Result<i32, Failure> {
+    let args = Args::parse(args, &["json"], &["proto"])?;
+    let port: u16 = args.arg(0, "port")?;
+    let protocol = args.protocol()?;
+    let wanted = |p: Protocol, n: u16| n == port && protocol.is_none_or(|proto| p == proto);
+    let sockets: Vec<PortInfo> = list_ports().into_iter().filter(|s| wanted(s.protocol, s.port)).collect();
+    let reservations: Vec<Reservation> =
+        Store::open()?.reservations()?.into_iter().filter(|r| wanted(r.protocol, r.port)).collect();
+    let found = !sockets.is_empty() || !reservations.is_empty();
+    let who = Who { port, sockets, reservations };
+    output(args.has("json"), &who, || {
+        let mut lines = Vec::new();
+        for s in &who.sockets {
+            let mut line = format!("{} {} {}", s.protocol, s.local_addr, s.state);
+            if let Some(pid) = s.pid {
+                line.push_str(&format!(" pid {} ({})", pid, s.process.as_deref().unwrap_or("-")));
+            }
+            if let Some(user) = &s.user {
+                line.push_str(&format!(" user {}", user));
+            }
+            lines.push(line);
+        }
+        for r in &who.reservations {
+            lines.push(format!("{} {}", r.key(), describe(r)));
+        }
+        lines.join("\n")
+    });
+    if !found {
+        if !args.has("json") {
+            eprintln!("port-manager: nothing holds or reserves port {}", port);
+        }
+        return Ok(EXIT_NOT_FOUND);
+    }
+    Ok(EXIT_OK)
+}
+
+/// The request shared by `reserve` and `allocate`
+fn request(args: &Args, service: String) -> Result<ReservationRequest, Failure> {
+    Ok(ReservationRequest {
+        protocol: args.protocol()?.unwrap_or_default(),
+        ttl: args.ttl()?,
+        pool: args.get("pool").map(String::from),
+        ..ReservationRequest::new(service)
+    })
+}
+
+fn reserve(args: &[String]) -> Result<i32, Failure> {
+    let args = Args::parse(args, &["json"], &["proto", "ttl", "pool"])?;
+    let port: u16 = args.arg(0, "port")?;
+    let service: String = args.arg(1, "service")?;
+    let r = Store::open()?.reserve(port, request(&args, service)?)?;
+    output(args.has("json"), &r, || format!("Reserved {} for '{}'.", r.key(), r.service));
+    Ok(EXIT_OK)
+}
+
+fn release(args: &[String]) -> Result<i32, Failure> {
+    let args = Args::parse(args, &["json"], &["proto"])?;
+    let port: u16 = args.arg(0, "port")?;
+    let r = Store::open()?.release(args.protocol()?.unwrap_or_default(), port)?;
+    output(args.has("json"), &r, || format!("Released {} ('{}').", r.key(), r.service));
+    Ok(EXIT_OK)
+}
+
+fn allocate(args: &[String]) -> Result<i32, Failure> {
+    let args = Args::parse(args, &["json"], &["proto", "ttl", "pool", "range"])?;
+    let range: Option<PortRange> = args.value("range")?;
+    if range.is_none() && !args.has("pool") {
+        return Err(Failure::usage("allocate needs --range or --pool"));
+    }
+    let service = args.positional.first().cloned().unwrap_or_else(|| "port-manager".to_string());
+    let r = Store::open()?.allocate(range, request(&args, service)?)?;
+    // Just the number, for PORT=$(port-manager allocate --range 3000-3999)
+    output(args.has("json"), &r, || r.port.to_string());
+    Ok(EXIT_OK)
+}
+
+/// `TERM`, `SIGTERM` and `sigterm` all name the same signal
+fn parse_signal(name: &str) -> Result<Signal, Failure> {
+    port_manager_core::parse_signal(name).map_err(Failure::usage)
+}
+
+#[derive(Serialize)]
+struct Signalled {
+    pid: i32,
+    process_name: Option<String>,
+    signal: &'static str,
+}
+
+/// A process the signal couldn't be sent to
+#[derive(Serialize)]
+struct SignalFailure {
+    pid: i32,
+    process_name: Option<String>,
+    #[serde(flatten)]
+    reason: Failure,
+}
+
+#[derive(Serialize)]
+struct Freed {
+    port: u16,
+    protocol: Protocol,
+    killed: Vec<Signalled>,
+    failed: Vec<SignalFailure>,
+}
+
+fn kill_port(args: &[String]) -> Result<i32, Failure> {
+    let args = Args::parse(args, &["json"], &["proto", "signal"])?;
+    let port: u16 = args.arg(0, "port")?;
+    let protocol = args.protocol()?.unwrap_or_default();
+    let signal = parse_signal(args.get("signal").unwrap_or("TERM"))?;
+    let owners = port_owners(protocol, port);
+    if owners.is_empty() {
+        return Err(Failure::new("NOT_FOUND", format!("No process we can see holds {} port {}.", protocol, port)));
+    }
+    if let Some((pid, _)) = owners.iter().find(|(pid, _)| is_protected(*pid)) {
+        return Err(Failure::new("PROTECTED_PROCESS", format!("PID {} may not be signalled.", pid)));
+    }
+    let manager = local_manager().ok();
+    let entry = AuditEntry::new(actor(), "free").port(protocol, port).detail(signal.as_str());
+    let (mut killed, mut failed) = (Vec::new(), Vec::new());
+    for (pid, process_name) in owners {
+        let result = kill(Pid::from_raw(pid), signal);
+        if let Some(manager) = &manager {
+            let entry = entry.clone().pid(pid);
+            audit(manager, match result {
+                Ok(()) => entry,
+                Err(errno) => entry.failed(format!("{:?}", errno), errno.desc()),
+            });
+        }
+        let reason = match result {
+            Ok(()) => {
+                killed.push(Signalled { pid, process_name, signal: signal.as_str() });
+                continue;
+            }
+            // Gone already, which is what we wanted
+            Err(Errno::ESRCH) => continue,
+            Err(Errno::EPERM) => Failure::new("FORBIDDEN", format!("Not allowed to signal PID {}.", pid)),
+            Err(errno) => Failure::new("ERROR", format!("Signalling PID {} failed: {}", pid, errno.desc())),
+        };
+        failed.push(SignalFailure { pid, process_name, reason });
+    }
+    let freed = Freed { port, protocol, killed, failed };
+    output(args.has("json"), &freed, || {
+        let mut lines = Vec::new();
+        if !freed.killed.is_empty() {
+            let pids: Vec<String> = freed.killed.iter().map(|s| s.pid.to_string()).collect();
+            lines.push(format!("Sent {} to PID {}.", signal.as_str(), pids.join(", ")));
+        }
+        lines.extend(freed.failed.iter().map(|f| f.reason.message.clone()));
+        lines.join("\n")
+    });
+    // Any process left holding the port fails the command, with the first reason's code
+    Ok(freed.failed.first().map_or(EXIT_OK, |f| f.reason.exit_code()))
+}
+
+#[derive(Serialize)]
+struct WaitedFree {
+    port: u16,
+    protocol: Protocol,
+    waited_ms: u128,
+}
+
+fn wait_free(args: &[String]) -> Result<i32, Failure> {
+    let args = Args::parse(args, &["json"], &["proto", "timeout"])?;
+    let port: u16 = args.arg(0, "port")?;
+    let protocol = args.protocol()?.unwrap_or_default();
+    let timeout = args.value::<u64>("timeout")?.map_or(DEFAULT_WAIT, Duration::from_secs);
+    let started = Instant::now();
+    while is_port_in_use(protocol, port, None) {
+        if !timeout.is_zero() && started.elapsed() >= timeout {
+            let message = format!("{} port {} still in use after {}s.", protocol, port, timeout.as_secs());
+            return Err(Failure::new("TIMEOUT", message));
+        }
+        std::thread::sleep(POLL_INTERVAL);
+    }
+    let waited = WaitedFree { port, protocol, waited_ms: started.elapsed().as_millis() };
+    output(args.has("json"), &waited, String::new);
+    Ok(EXIT_OK)
+}
+
+/// Last signal `run` caught, to pass on to its command; 0 for none
+static CAUGHT: AtomicI32 = AtomicI32::new(0);
+
+extern "C" fn catch_signal(signal: nix::libc::c_int) {
+    CAUGHT.store(signal, Ordering::SeqCst);
+}
+
+/// Stay alive through SIGINT, SIGTERM and SIGHUP so the port is released after the
+/// command exits. The command gets default handlers back when it execs.
+fn catch_signals() -> Result<(), Failure> {
+    let action = SigAction::new(SigHandler::Handler(catch_signal), SaFlags::SA_RESTART, SigSet::empty());
+    for signal in [Signal::SIGINT, Signal::SIGTERM, Signal::SIGHUP] {
+        // SAFETY: the handler only stores to an atomic
+        unsafe { sigaction(signal, &action) }.map_err(|e| Failure::new("ERROR", format!("sigaction: {}", e)))?;
+    }
+    Ok(())
+}
+
+/// Whether `pid` is `ancestor` or one of its descendants, following parents in `/proc`
+fn descends_from(mut pid: i32, ancestor: i32) -> bool {
+    // Deep enough for `npm start` and friends, and bounded in case of a race with reused PIDs
+    for _ in 0..64 {
+        if pid == ancestor {
+            return true;
+        }
+        if pid <= 1 {
+            return false;
+        }
+        let Ok(stat) = std::fs::read_to_string(format!("/proc/{}/stat", pid)) else { return false };
+        // The command name may hold spaces and parentheses, so read the fields after the last ')'
+        let ppid = stat.rsplit_once(')').and_then(|(_, rest)| rest.split_whitespace().nth(1)?.parse().ok());
+        match ppid {
+            Some(ppid) => pid = ppid,
+            None => return false,
+        }
+    }
+    false
+}
+
+/// The shell convention: the exit code, or 128 plus the signal that killed it
+fn status_code(status: ExitStatus) -> i32 {
+    status.code().unwrap_or_else(|| 128 + status.signal().unwrap_or(0))
+}
+
+fn run_command(args: &[String]) -> Result<i32, Failure> {
+    let args = Args::parse(args, &[], &["pool", "range", "service", "env", "ttl", "proto", "bind-timeout"])?;
+    if args.positional.is_empty() {
+        return Err(Failure::usage("run needs a command after --"));
+    }
+    let range: Option<PortRange> = args.value("range")?;
+    if range.is_none() && !args.has("pool") {
+        return Err(Failure::usage("run needs --range or --pool"));
+    }
+    let program = &args.positional[0];
+    let service = match args.get("service") {
+        Some(service) => service.to_string(),
+        None => program.rsplit('/').next().unwrap_or(program).to_string(),
+    };
+    let store = Store::open()?;
+    let reservation = store.allocate(range, request(&args, service)?)?;
+    let result = supervise(&store, &reservation, &args);
+    if let Err(e) = store.release_exact(&reservation) {
+        eprintln!("port-manager: could not release {}: {}", reservation.key(), e.message);
+    }
+    result
+}
+
+/// Start the command on the reserved port and wait for it, passing on signals, keeping
+/// the lease alive and checking that the port gets bound
+fn supervise(store: &Store, r: &Reservation, args: &Args) -> Result<i32, Failure> {
+    let var = args.get("env").unwrap_or("PORT");
+    let port = r.port.to_string();
+    let argv: Vec<String> = args.positional.iter().map(|a| a.replace(PORT_PLACEHOLDER, &port)).collect();
+    let bind_wait = args.value::<u64>("bind-timeout")?.map_or(DEFAULT_BIND_WAIT, Duration::from_secs);
+    catch_signals()?;
+    eprintln!("port-manager: {}={} reserved for '{}'", var, port, r.service);
+    let mut child = match Command::new(&argv[0]).args(&argv[1..]).env(var, &port).spawn() {
+        Ok(child) => child,
+        Err(e) => {
+            eprintln!("port-manager: {}: {}", argv[0], e);
+            // What shells return for a command they can't run
+            return Ok(127);
+        }
+    };
+    let child_pid = child.id() as i32;
+    let started = Instant::now();
+    let renew_every = r.ttl_secs.map(|ttl| Duration::from_secs((ttl / 3).max(1)));
+    let (mut renewed, mut checked) = (Instant::now(), None::<Instant>);
+    let (mut bound, mut warned) = (false, false);
+    loop {
+        if let Some(status) = child.try_wait().map_err(|e| Failure::new("ERROR", e.to_string()))? {
+            if !bound {
+                eprintln!("port-manager: {} exited without binding {}", argv[0], r.key());
+            }
+            return Ok(status_code(status));
+        }
+        // Ctrl-C already reached the command through the terminal; pass on anything else
+        match Signal::try_from(CAUGHT.swap(0, Ordering::SeqCst)) {
+            Ok(Signal::SIGINT) | Err(_) => {}
+            Ok(signal) => {
+                let _ = kill(Pid::from_raw(child_pid), signal);
+            }
+        }
+        if !bound && checked.is_none_or(|at| at.elapsed() >= BIND_CHECK_INTERVAL) {
+            checked = Some(Instant::now());
+            let holders: Vec<PortInfo> = list_ports()
+                .into_iter()
+                .filter(|s| s.protocol == r.protocol && s.port == r.port)
+                .filter(|s| s.state == "Listen" || s.protocol == Protocol::Udp)
+                .collect();
+            if let Some(s) = holders.iter().find(|s| s.pid.is_some_and(|pid| descends_from(pid, child_pid))) {
+                bound = true;
+                let process = s.process.as_deref().unwrap_or("-");
+                eprintln!("port-manager: {} bound by PID {} ({})", r.key(), s.pid.unwrap_or_default(), process);
+            } else if !warned && started.elapsed() >= bind_wait {
+                warned = true;
+                let others: Vec<String> = holders.iter().filter_map(|s| s.pid).map(|pid| pid.to_string()).collect();
+                let note = if others.is_empty() { String::new() } else { format!("; PID {} holds it", others.join(", ")) };
+                eprintln!("port-manager: {} has not bound {} after {}s{}", argv[0], r.key(), bind_wait.as_secs(), note);
+            }
+        }
+        if let Some(every) = renew_every {
+            if renewed.elapsed() >= every {
+                renewed = Instant::now();
+                if let Err(e) = store.renew(r.key()) {
+                    eprintln!("port-manager: could not renew the lease on {}: {}", r.key(), e.message);
+                }
+            }
+        }
+        std::thread::sleep(POLL_INTERVAL);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn exit_code(code: &str) -> i32 {
+        Failure::new(code, "").exit_code()
+    }
+
+    #[test]
+    fn maps_every_store_error_code() {
+        let errors = [
+            (ReservationError::AlreadyReserved(String::new()), EXIT_CONFLICT),
+            (ReservationError::InUse(String::new()), EXIT_CONFLICT),
+            (ReservationError::NotFound(String::new()), EXIT_NOT_FOUND),
+            (ReservationError::Forbidden(String::new()), EXIT_DENIED),
+            (ReservationError::LimitReached(String::new()), EXIT_CONFLICT),
+            (ReservationError::NoFreePort(String::new()), EXIT_CONFLICT),
+            (ReservationError::Invalid(String::new()), EXIT_USAGE),
+            (ReservationError::Storage(String::new()), EXIT_ERROR),
+        ];
+        for (error, expected) in errors {
+            let code = error.code();
+            assert_eq!(Failure::from(error).exit_code(), expected, "{}", code);
+        }
+    }
+
+    #[test]
+    fn maps_the_codes_only_the_api_sends() {
+        assert_eq!(exit_code("UNAUTHORIZED"), EXIT_DENIED);
+        assert_eq!(exit_code("PROTECTED_PROCESS"), EXIT_DENIED);
+        assert_eq!(exit_code("RATE_LIMITED"), EXIT_RATE_LIMITED);
+        assert_eq!(exit_code("INTERNAL"), EXIT_ERROR);
+        assert_eq!(exit_code("HTTP_502"), EXIT_ERROR);
+    }
+
+    #[test]
+    fn maps_cli_and_daemon_failures() {
+        assert_eq!(exit_code("USAGE"), EXIT_USAGE);
+        assert_eq!(exit_code("TIMEOUT"), EXIT_TIMEOUT);
+        assert_eq!(exit_code("ERROR"), EXIT_ERROR);
+        let unreachable = Failure::from(DaemonError::Unreachable("connection refused".to_string()));
+        assert_eq!((unreachable.code.as_str(), unreachable.exit_code()), ("UNREACHABLE", EXIT_UNREACHABLE));
+        let refused = DaemonError::Api { code: "RATE_LIMITED".to_string(), message: "slow down".to_string() };
+        assert_eq!(Failure::from(refused).exit_code(), EXIT_RATE_LIMITED);
+    }
+
+    #[test]
+    fn follows_parents_in_proc() {
+        let me = std::process::id() as i32;
+        let parent = nix::unistd::getppid().as_raw();
+        assert!(descends_from(me, me));
+        assert!(descends_from(me, parent));
+        assert!(!descends_from(parent, me));
+        let mut child = Command::new("sleep").arg("5").spawn().unwrap();
+        let pid = child.id() as i32;
+        assert!(descends_from(pid, me) && descends_from(pid, parent));
+        child.kill().unwrap();
+        child.wait().unwrap();
+        // Reaped, so it has no /proc entry to follow any more
+        assert!(!descends_from(pid, me));
+    }
+
+    #[test]
+    fn maps_child_status_to_exit_codes() {
+        // Raw wait statuses: the exit code in the second byte, or the signal in the first
+        assert_eq!(status_code(ExitStatus::from_raw(0)), 0);
+        assert_eq!(status_code(ExitStatus::from_raw(3 << 8)), 3);
+        assert_eq!(status_code(ExitStatus::from_raw(255 << 8)), 255);
+        assert_eq!(status_code(ExitStatus::from_raw(Signal::SIGKILL as i32)), 137);
+        assert_eq!(status_code(ExitStatus::from_raw(Signal::SIGTERM as i32)), 143);
+    }
+}
@@ -0,0 +1,181 @@
+use crate::net::Protocol;
+use crate::reservation::unix_now;
+use rusqlite::{params, Connection, Row};
+use serde::{Deserialize, Serialize};
+
+/// `result` of an action that succeeded; failures record their error code instead
+pub const AUDIT_OK: &str = "ok";
+
+/// One row of the append-only audit log: who did what to which port or process, and how
+/// it went
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct AuditEntry {
+    /// Assigned by the store; 0 until recorded
+    pub id: i64,
+    /// Unix seconds
+    pub timestamp: u64,
+    #[cfg_attr(feature = "openapi", schema(example = "alice"))]
+    pub actor: String,
+    #[cfg_attr(feature = "openapi", schema(example = "reserve"))]
+    pub action: String,
+    pub protocol: Option<Protocol>,
+    pub port: Option<u16>,
+    pub pid: Option<i32>,
+    /// `ok`, or the error code of a refused or failed action
+    #[cfg_attr(feature = "openapi", schema(example = "ok"))]
+    pub result: String,
+    /// Service name, signal, error message and the like
+    pub detail: Option<String>,
+}
+
+impl AuditEntry {
+    /// A successful `action` by `actor`, happening now
+    pub fn new(actor: impl Into<String>, action: impl Into<String>) -> Self {
+        AuditEntry {
+            id: 0,
+            timestamp: unix_now(),
+            actor: actor.into(),
+            action: action.into(),
+            protocol: None,
+            port: None,
+            pid: None,
+            result: AUDIT_OK.to_string(),
+            detail: None,
+        }
+    }
+
+    pub fn port(mut self, protocol: Protocol, port: u16) -> Self {
+        self.protocol = Some(protocol);
+        self.port = Some(port);
+        self
+    }
+
+    pub fn pid(mut self, pid: i32) -> Self {
+        self.pid = Some(pid);
+        self
+    }
+
+    pub fn detail(mut self, detail: impl Into<String>) -> Self {
+        self.detail = Some(detail.into());
+        self
+    }
+
+    /// Mark the action as failed with `code`, keeping `message` as the detail
+    pub fn failed(mut self, code: impl Into<String>, message: impl Into<String>) -> Self {
+        self.result = code.into();
+        self.detail = Some(message.into());
+        self
+    }
+
+    fn from_row(row: &Row) -> rusqlite::Result<Self> {
+        Ok(AuditEntry {
+            id: row.get(0)?,
+            timestamp: row.get(1)?,
+            actor: row.get(2)?,
+            action: row.get(3)?,
+            protocol: row.get(4)?,
+            port: row.get(5)?,
+            pid: row.get(6)?,
+            result: row.get(7)?,
+            detail: row.get(8)?,
+        })
+    }
+}
+
+/// Narrows `PortReservationManager::audit_log`; every given field must match
+#[derive(Debug, Clone, Default, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::IntoParams))]
+#[cfg_attr(feature = "openapi", into_params(parameter_in = Query))]
+pub struct AuditQuery {
+    pub actor: Option<String>,
+    pub action: Option<String>,
+    pub port: Option<u16>,
+    pub pid: Option<i32>,
+    /// Only entries at or after this Unix time
+    pub since: Option<u64>,
+    /// Most entries to return, newest first; defaults to `DEFAULT_AUDIT_LIMIT`
+    pub limit: Option<u32>,
+}
+
+pub const DEFAULT_AUDIT_LIMIT: u32 = 100;
+
+pub(crate) fn insert(conn: &Connection, entry: &AuditEntry) -> rusqlite::Result<i64> {
+    conn.execute(
+        "INSERT INTO audit (timestamp, actor, action, protocol, port, pid, result, detail)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+        params![
+            entry.timestamp,
+            entry.actor,
+            entry.action,
+            entry.protocol,
+            entry.port,
+            entry.pid,
+            entry.result,
+            entry.detail,
+        ],
+    )?;
+    Ok(conn.last_insert_rowid())
+}
+
+pub(crate) fn query(conn: &Connection, q: &AuditQuery) -> rusqlite::Result<Vec<AuditEntry>> {
+    let mut stmt = conn.prepare(
+        "SELECT id, timestamp, actor, action, protocol, port, pid, result, detail FROM audit
+         WHERE (?1 IS NULL OR actor = ?1)
+           AND (?2 IS NULL OR action = ?2)
+           AND (?3 IS NULL OR port = ?3)
+           AND (?4 IS NULL OR pid = ?4)
+           AND (?5 IS NULL OR timestamp >= ?5)
+         ORDER BY id DESC LIMIT ?6",
+    )?;
+    let limit = q.limit.unwrap_or(DEFAULT_AUDIT_LIMIT);
+    let rows = stmt.query_map(params![q.actor, q.action, q.port, q.pid, q.since, limit], AuditEntry::from_row)?;
+    rows.collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PortReservationManager;
+
+    #[test]
+    fn the_log_is_append_only() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("reservations.db");
+        let manager = PortReservationManager::open(&path).unwrap();
+        let id = manager.record_audit(&AuditEntry::new("alice", "reserve").port(Protocol::Tcp, 8080)).unwrap();
+        // Another connection, as a user with sqlite3 would have
+        let conn = Connection::open(&path).unwrap();
+        let err = conn.execute("UPDATE audit SET actor = 'mallory' WHERE id = ?1", [id]).unwrap_err();
+        assert!(err.to_string().contains("append-only"), "{}", err);
+        let err = conn.execute("DELETE FROM audit", []).unwrap_err();
+        assert!(err.to_string().contains("append-only"), "{}", err);
+        let log = manager.audit_log(&AuditQuery::default()).unwrap();
+        assert_eq!((log.len(), log[0].actor.as_str()), (1, "alice"));
+    }
+
+    #[test]
+    fn filters_by_every_given_field() {
+        let manager = PortReservationManager::new();
+        let at = |timestamp, entry: AuditEntry| manager.record_audit(&AuditEntry { timestamp, ..entry }).unwrap();
+        let reserve = at(100, AuditEntry::new("alice", "reserve").port(Protocol::Tcp, 8080));
+        let release = at(200, AuditEntry::new("alice", "release").port(Protocol::Tcp, 8080));
+        let other = at(300, AuditEntry::new("bob", "reserve").port(Protocol::Udp, 9090));
+        let signal = at(400, AuditEntry::new("bob", "signal").pid(4242).failed("FORBIDDEN", "nope"));
+        let ids = |q: AuditQuery| manager.audit_log(&q).unwrap().iter().map(|e| e.id).collect::<Vec<_>>();
+
+        assert_eq!(ids(AuditQuery::default()), [signal, other, release, reserve]);
+        assert_eq!(ids(AuditQuery { actor: Some("alice".to_string()), ..Default::default() }), [release, reserve]);
+        assert_eq!(ids(AuditQuery { port: Some(8080), ..Default::default() }), [release, reserve]);
+        assert_eq!(ids(AuditQuery { action: Some("reserve".to_string()), ..Default::default() }), [other, reserve]);
+        assert_eq!(ids(AuditQuery { pid: Some(4242), ..Default::default() }), [signal]);
+        assert_eq!(ids(AuditQuery { since: Some(200), ..Default::default() }), [signal, other, release]);
+        assert_eq!(ids(AuditQuery { limit: Some(2), ..Default::default() }), [signal, other]);
+        let both = AuditQuery { actor: Some("bob".to_string()), action: Some("reserve".to_string()), ..Default::default() };
+        assert_eq!(ids(both), [other]);
+        assert!(ids(AuditQuery { actor: Some("carol".to_string()), ..Default::default() }).is_empty());
+
+        let failed = &manager.audit_log(&AuditQuery { pid: Some(4242), ..Default::default() }).unwrap()[0];
+        assert_eq!((failed.result.as_str(), failed.detail.as_deref()), ("FORBIDDEN", Some("nope")));
+    }
+}
@@ -0,0 +1,24 @@
+//! Shared port discovery and reservation logic used by the TUI, the API
+//! server and the egui app.
+
+pub mod audit;
+pub mod net;
+pub mod pool;
+pub mod reservation;
+pub mod watch;
+
+pub use audit::{AuditEntry, AuditQuery, AUDIT_OK, DEFAULT_AUDIT_LIMIT};
+pub use net::{
+    addrs_overlap, bound_ports, bound_sockets, is_port_in_use, is_protected, list_ports, port_owners, process_name,
+    time_wait_count, well_known_service, PortInfo, Protocol, SortBy,
+};
+#[cfg(unix)]
+pub use net::parse_signal;
+pub use pool::{default_pools_path, load_pools, Pool};
+pub use reservation::{
+    config_dir, default_socket_paths, default_store_path, state_dir, uid_owner, unix_now, PortRange,
+    PortReservationManager, Reservation, ReservationError, ReservationKey, ReservationRequest, ReservationUpdate,
+};
+#[cfg(unix)]
+pub use reservation::local_owner;
+pub use watch::{PortEvent, PortWatcher};
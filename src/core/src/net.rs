@@ -0,0 +1,578 @@
+#[cfg(target_os = "linux")]
+use procfs::process::{all_processes, FDTarget};
+#[cfg(target_os = "linux")]
+use procfs::net::{tcp, tcp6, udp, udp6};
+#[cfg(any(target_os = "macos", target_os = "windows"))]
+use netstat2::ProtocolSocketInfo;
+use netstat2::{get_sockets_info, AddressFamilyFlags, ProtocolFlags};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashSet};
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+
+/// Transport protocol of a socket or reservation, written `TCP`/`UDP` (parsed case-insensitively)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum Protocol {
+    #[default]
+    Tcp,
+    Udp,
+}
+
+impl Protocol {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Protocol::Tcp => "TCP",
+            Protocol::Udp => "UDP",
+        }
+    }
+}
+
+impl fmt::Display for Protocol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Protocol {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("tcp") {
+            Ok(Protocol::Tcp)
+        } else if s.eq_ignore_ascii_case("udp") {
+            Ok(Protocol::Udp)
+        } else {
+            Err(format!("Unknown protocol '{}'; expected TCP or UDP.", s))
+        }
+    }
+}
+
+impl TryFrom<String> for Protocol {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<Protocol> for String {
+    fn from(protocol: Protocol) -> Self {
+        protocol.as_str().to_string()
+    }
+}
+
+#[cfg(feature = "openapi")]
+impl utoipa::PartialSchema for Protocol {
+    fn schema() -> utoipa::openapi::RefOr<utoipa::openapi::schema::Schema> {
+        utoipa::openapi::ObjectBuilder::new()
+            .schema_type(utoipa::openapi::Type::String)
+            .enum_values(Some(["TCP", "UDP"]))
+            .description(Some("Transport protocol, matched case-insensitively"))
+            .into()
+    }
+}
+
+#[cfg(feature = "openapi")]
+impl utoipa::ToSchema for Protocol {}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct PortInfo {
+    pub local_addr: String,
+    pub remote_addr: String,
+    pub state: String,
+    pub pid: Option<i32>,
+    #[serde(rename = "process_name")]
+    pub process: Option<String>,
+    pub protocol: Protocol,
+    pub port: u16,
+    pub tags: Vec<String>,
+    pub user: Option<String>,
+}
+
+impl PortInfo {
+    /// IP part of `local_addr`
+    pub fn local_ip(&self) -> Option<IpAddr> {
+        self.local_addr.rsplit_once(':').and_then(|(ip, _)| ip.parse().ok())
+    }
+
+    /// Case-insensitive search over the addresses (and so the port), state, PID and process name
+    pub fn matches(&self, query: &str) -> bool {
+        let query = query.to_lowercase();
+        self.local_addr.to_lowercase().contains(&query)
+            || self.remote_addr.to_lowercase().contains(&query)
+            || self.state.to_lowercase().contains(&query)
+            || self.pid.is_some_and(|pid| pid.to_string().contains(&query))
+            || self.process.as_ref().map(|p| p.to_lowercase().contains(&query)).unwrap_or(false)
+    }
+}
+
+/// Orders for a list of sockets, shared by the TUI and the API's `?sort=`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum SortBy {
+    #[default]
+    Port,
+    Process,
+    Protocol,
+    State,
+    Pid,
+}
+
+impl SortBy {
+    /// Stable sort of `ports`, with ties kept in port order
+    pub fn sort(self, ports: &mut [PortInfo]) {
+        ports.sort_by(|a, b| {
+            let by_port = a.port.cmp(&b.port).then_with(|| a.local_addr.cmp(&b.local_addr));
+            match self {
+                SortBy::Port => by_port,
+                SortBy::Process => a.process.cmp(&b.process).then(by_port),
+                SortBy::Protocol => a.protocol.cmp(&b.protocol).then(by_port),
+                SortBy::State => a.state.cmp(&b.state).then(by_port),
+                SortBy::Pid => a.pid.cmp(&b.pid).then(by_port),
+            }
+        });
+    }
+}
+
+/// Ports of common services, for the `well-known` tag
+const WELL_KNOWN_SERVICES: &[(u16, &str)] = &[
+    (20, "ftp-data"),
+    (21, "ftp"),
+    (22, "ssh"),
+    (23, "telnet"),
+    (25, "smtp"),
+    (53, "dns"),
+    (67, "dhcp"),
+    (68, "dhcp"),
+    (80, "http"),
+    (110, "pop3"),
+    (123, "ntp"),
+    (143, "imap"),
+    (161, "snmp"),
+    (389, "ldap"),
+    (443, "https"),
+    (465, "smtps"),
+    (514, "syslog"),
+    (587, "submission"),
+    (631, "ipp"),
+    (636, "ldaps"),
+    (993, "imaps"),
+    (995, "pop3s"),
+    (1433, "mssql"),
+    (1883, "mqtt"),
+    (2049, "nfs"),
+    (3306, "mysql"),
+    (3389, "rdp"),
+    (5353, "mdns"),
+    (5432, "postgresql"),
+    (5672, "amqp"),
+    (6379, "redis"),
+    (6443, "kubernetes"),
+    (8080, "http-alt"),
+    (8443, "https-alt"),
+    (9090, "prometheus"),
+    (9200, "elasticsearch"),
+    (11211, "memcached"),
+    (27017, "mongodb"),
+];
+
+/// Name of the service that conventionally listens on `port`
+pub fn well_known_service(port: u16) -> Option<&'static str> {
+    WELL_KNOWN_SERVICES.iter().find(|(p, _)| *p == port).map(|(_, name)| *name)
+}
+
+/// Tags `list_ports` can tell from the socket and its process. `reserved` needs the
+/// reservation store and is added by `PortReservationManager::tag_reserved`.
+fn socket_tags(port: u16, local_ip: Option<IpAddr>, in_container: bool) -> Vec<String> {
+    let mut tags = Vec::new();
+    if port != 0 && port < 1024 {
+        tags.push("privileged".to_string());
+    }
+    if well_known_service(port).is_some() {
+        tags.push("well-known".to_string());
+    }
+    if local_ip.is_some_and(|ip| ip.is_loopback()) {
+        tags.push("loopback-only".to_string());
+    }
+    if in_container {
+        tags.push("container".to_string());
+    }
+    tags
+}
+
+/// Usernames by UID, from `/etc/passwd`
+#[cfg(target_os = "linux")]
+fn usernames() -> std::collections::HashMap<u32, String> {
+    std::fs::read_to_string("/etc/passwd").map(|passwd| parse_passwd(&passwd)).unwrap_or_default()
+}
+
+/// Usernames by UID from `passwd(5)` lines (`name:password:uid:...`), skipping any that
+/// don't parse
+#[cfg(target_os = "linux")]
+fn parse_passwd(passwd: &str) -> std::collections::HashMap<u32, String> {
+    passwd
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split(':');
+            let name = fields.next()?;
+            let uid = fields.nth(1)?.parse().ok()?;
+            Some((uid, name.to_string()))
+        })
+        .collect()
+}
+
+/// Whether `pid` runs in a Docker, Podman, containerd, Kubernetes or LXC cgroup
+#[cfg(target_os = "linux")]
+fn in_container(pid: i32) -> bool {
+    const RUNTIMES: &[&str] = &["docker", "containerd", "kubepods", "libpod", "lxc"];
+    std::fs::read_to_string(format!("/proc/{}/cgroup", pid))
+        .map(|cgroup| RUNTIMES.iter().any(|r| cgroup.contains(r)))
+        .unwrap_or(false)
+}
+
+/// Command name of `pid`, as `list_ports` reports it
+pub fn process_name(pid: i32) -> Option<String> {
+    #[cfg(target_os = "linux")]
+    {
+        procfs::process::Process::new(pid).and_then(|p| p.stat()).ok().map(|s| s.comm)
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = pid;
+        None
+    }
+}
+
+/// Processes with a `protocol` socket on `port`, by PID. Sockets whose owner we can't
+/// see are left out.
+pub fn port_owners(protocol: Protocol, port: u16) -> BTreeSet<(i32, Option<String>)> {
+    list_ports()
+        .into_iter()
+        .filter(|p| p.protocol == protocol && p.port == port)
+        .filter_map(|p| Some((p.pid?, p.process)))
+        .collect()
+}
+
+/// PID 1 and the calling process, which are never signalled on a user's behalf
+pub fn is_protected(pid: i32) -> bool {
+    pid <= 1 || pid as u32 == std::process::id()
+}
+
+/// `TERM`, `SIGTERM` and `sigterm` all name the same signal
+#[cfg(unix)]
+pub fn parse_signal(name: &str) -> Result<nix::sys::signal::Signal, String> {
+    let name = name.trim().to_ascii_uppercase();
+    let full = if name.starts_with("SIG") { name } else { format!("SIG{}", name) };
+    nix::sys::signal::Signal::from_str(&full).map_err(|_| format!("Unknown signal '{}'.", full))
+}
+
+/// Whether a socket bound to `a` and one bound to `b` would clash. `None` stands for
+/// "any address". The unspecified address covers its whole family, and `::` also covers
+/// IPv4 because Linux binds it dual-stack by default.
+pub fn addrs_overlap(a: Option<IpAddr>, b: Option<IpAddr>) -> bool {
+    let (a, b) = match (a, b) {
+        (Some(a), Some(b)) => (a, b),
+        _ => return true,
+    };
+    if a == b {
+        return true;
+    }
+    let covers = |wide: IpAddr, other: IpAddr| match wide {
+        IpAddr::V6(v6) if v6.is_unspecified() => true,
+        IpAddr::V4(v4) if v4.is_unspecified() => other.is_ipv4(),
+        _ => false,
+    };
+    covers(a, b) || covers(b, a)
+}
+
+/// Checks if `port` is bound by a `protocol` socket whose address overlaps `addr`
+/// (any address when `None`), across IPv4 and IPv6 (cross-platform)
+pub fn is_port_in_use(protocol: Protocol, port: u16, addr: Option<IpAddr>) -> bool {
+    bound_sockets(protocol)
+        .iter()
+        .any(|s| s.port() == port && addrs_overlap(addr, Some(s.ip())))
+}
+
+/// Ports bound by `protocol` sockets whose address overlaps `addr`
+pub fn bound_ports(protocol: Protocol, addr: Option<IpAddr>) -> HashSet<u16> {
+    bound_sockets(protocol)
+        .iter()
+        .filter(|s| addrs_overlap(addr, Some(s.ip())))
+        .map(|s| s.port())
+        .collect()
+}
+
+/// Every local address bound by a `protocol` socket, including sockets owned by
+/// processes we can't inspect (which `list_ports` leaves out)
+pub fn bound_sockets(protocol: Protocol) -> Vec<SocketAddr> {
+    let af_flags = AddressFamilyFlags::IPV4 | AddressFamilyFlags::IPV6;
+    let proto_flags = match protocol {
+        Protocol::Tcp => ProtocolFlags::TCP,
+        Protocol::Udp => ProtocolFlags::UDP,
+    };
+    get_sockets_info(af_flags, proto_flags)
+        .map(|sockets| sockets.iter().map(|info| SocketAddr::new(info.local_addr(), info.local_port())).collect())
+        .unwrap_or_default()
+}
+
+/// TCP sockets in TIME_WAIT. They belong to no process, so `list_ports` leaves them out,
+/// but enough of them exhaust the ephemeral port range.
+pub fn time_wait_count() -> usize {
+    let af_flags = AddressFamilyFlags::IPV4 | AddressFamilyFlags::IPV6;
+    get_sockets_info(af_flags, ProtocolFlags::TCP)
+        .map(|sockets| {
+            sockets
+                .iter()
+                .filter(|info| match &info.protocol_socket_info {
+                    netstat2::ProtocolSocketInfo::Tcp(tcp) => tcp.state == netstat2::TcpState::TimeWait,
+                    _ => false,
+                })
+                .count()
+        })
+        .unwrap_or_default()
+}
+
+pub fn list_ports() -> Vec<PortInfo> {
+    #[cfg(target_os = "linux")]
+    {
+        let mut results = Vec::new();
+        let mut tcp_entries = Vec::new();
+        if let Ok(tcp4) = tcp() { tcp_entries.extend(tcp4); }
+        if let Ok(tcp6) = tcp6() { tcp_entries.extend(tcp6); }
+        let mut udp_entries = Vec::new();
+        if let Ok(u4) = udp() { udp_entries.extend(u4); }
+        if let Ok(u6) = udp6() { udp_entries.extend(u6); }
+        let tcp_map: std::collections::HashMap<u64, &procfs::net::TcpNetEntry> =
+            tcp_entries.iter().map(|e| (e.inode, e)).collect();
+        let udp_map: std::collections::HashMap<u64, &procfs::net::UdpNetEntry> =
+            udp_entries.iter().map(|e| (e.inode, e)).collect();
+        let usernames = usernames();
+        if let Ok(procs) = all_processes() {
+            for proc in procs.flatten() {
+                if let Ok(fds) = proc.fd() {
+                    let process = proc.stat().ok().map(|s| s.comm);
+                    // Fall back to the numeric UID for users missing from /etc/passwd
+                    let user = proc.uid().ok().map(|uid| usernames.get(&uid).cloned().unwrap_or_else(|| uid.to_string()));
+                    let container = in_container(proc.pid());
+                    for fd in fds.flatten() {
+                        if let FDTarget::Socket(inode) = fd.target {
+                            if let Some(entry) = tcp_map.get(&inode) {
+                                let local = format!("{}:{}", entry.local_address.ip(), entry.local_address.port());
+                                let remote = format!("{}:{}", entry.remote_address.ip(), entry.remote_address.port());
+                                results.push(PortInfo {
+                                    local_addr: local,
+                                    remote_addr: remote,
+                                    state: format!("{:?}", entry.state),
+                                    pid: Some(proc.pid()),
+                                    process: process.clone(),
+                                    protocol: Protocol::Tcp,
+                                    port: entry.local_address.port(),
+                                    tags: socket_tags(entry.local_address.port(), Some(entry.local_address.ip()), container),
+                                    user: user.clone(),
+                                });
+                            }
+                            if let Some(entry) = udp_map.get(&inode) {
+                                let local = format!("{}:{}", entry.local_address.ip(), entry.local_address.port());
+                                let remote = if entry.remote_address.port() == 0 { "-".into() }
+                                             else { format!("{}:{}", entry.remote_address.ip(), entry.remote_address.port()) };
+                                results.push(PortInfo {
+                                    local_addr: local,
+                                    remote_addr: remote,
+                                    state: "UDP".into(),
+                                    pid: Some(proc.pid()),
+                                    process: process.clone(),
+                                    protocol: Protocol::Udp,
+                                    port: entry.local_address.port(),
+                                    tags: socket_tags(entry.local_address.port(), Some(entry.local_address.ip()), container),
+                                    user: user.clone(),
+                                });
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        results
+    }
+    #[cfg(any(target_os = "macos", target_os = "windows"))]
+    {
+        let mut results = Vec::new();
+        let af_flags = AddressFamilyFlags::IPV4 | AddressFamilyFlags::IPV6;
+        let proto_flags = ProtocolFlags::TCP | ProtocolFlags::UDP;
+        if let Ok(sockets) = get_sockets_info(af_flags, proto_flags) {
+            for info in sockets {
+                match info.protocol_socket_info {
+                    ProtocolSocketInfo::Tcp(tcp) => {
+                        results.push(PortInfo {
+                            local_addr: format!("{}:{}", tcp.local_addr, tcp.local_port),
+                            remote_addr: format!("{}:{}", tcp.remote_addr, tcp.remote_port),
+                            state: format!("{:?}", tcp.state),
+                            pid: info.associated_pids.first().map(|&pid| pid as i32),
+                            process: None, // Not available cross-platform
+                            protocol: Protocol::Tcp,
+                            port: tcp.local_port,
+                            tags: socket_tags(tcp.local_port, Some(tcp.local_addr), false),
+                            user: None,
+                        });
+                    }
+                    ProtocolSocketInfo::Udp(udp) => {
+                        results.push(PortInfo {
+                            local_addr: format!("{}:{}", udp.local_addr, udp.local_port),
+                            remote_addr: "-".to_string(),
+                            state: "UDP".to_string(),
+                            pid: info.associated_pids.first().map(|&pid| pid as i32),
+                            process: None,
+                            protocol: Protocol::Udp,
+                            port: udp.local_port,
+                            tags: socket_tags(udp.local_port, Some(udp.local_addr), false),
+                            user: None,
+                        });
+                    }
+                }
+            }
+        }
+        results
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> Option<IpAddr> {
+        Some(s.parse().unwrap())
+    }
+
+    #[test]
+    fn unspecified_addresses_cover_their_family() {
+        assert!(addrs_overlap(ip("0.0.0.0"), ip("127.0.0.1")));
+        assert!(addrs_overlap(ip("10.0.0.1"), ip("0.0.0.0")));
+        assert!(!addrs_overlap(ip("0.0.0.0"), ip("::1")));
+        assert!(addrs_overlap(ip("::"), ip("::1")));
+        // `::` is dual-stack on Linux, so it takes IPv4 too
+        assert!(addrs_overlap(ip("::"), ip("127.0.0.1")));
+        assert!(addrs_overlap(ip("0.0.0.0"), ip("::")));
+    }
+
+    #[test]
+    fn distinct_addresses_dont_overlap() {
+        assert!(addrs_overlap(ip("127.0.0.1"), ip("127.0.0.1")));
+        assert!(!addrs_overlap(ip("127.0.0.1"), ip("10.0.0.1")));
+        assert!(!addrs_overlap(ip("::1"), ip("127.0.0.1")));
+    }
+
+    #[test]
+    fn none_means_every_address() {
+        assert!(addrs_overlap(None, None));
+        assert!(addrs_overlap(None, ip("127.0.0.1")));
+        assert!(addrs_overlap(ip("::1"), None));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn signal_names_ignore_case_and_prefix() {
+        use nix::sys::signal::Signal;
+        for name in ["TERM", "SIGTERM", "sigterm", " term "] {
+            assert_eq!(parse_signal(name), Ok(Signal::SIGTERM));
+        }
+        assert_eq!(parse_signal("kill"), Ok(Signal::SIGKILL));
+        assert_eq!(parse_signal("nope"), Err("Unknown signal 'SIGNOPE'.".to_string()));
+    }
+
+    #[test]
+    fn pid_1_and_ourselves_are_protected() {
+        assert!(is_protected(1));
+        assert!(is_protected(0));
+        assert!(is_protected(std::process::id() as i32));
+        assert!(!is_protected(std::process::id() as i32 + 1));
+    }
+
+    #[test]
+    fn tags_what_the_port_and_address_say() {
+        let tags = |port, ip: Option<&str>, container| socket_tags(port, ip.map(|ip| ip.parse().unwrap()), container);
+        assert_eq!(tags(22, Some("0.0.0.0"), false), ["privileged", "well-known"]);
+        assert_eq!(tags(80, Some("127.0.0.1"), false), ["privileged", "well-known", "loopback-only"]);
+        assert_eq!(tags(5432, Some("::1"), true), ["well-known", "loopback-only", "container"]);
+        assert_eq!(tags(40000, Some("::"), true), ["container"]);
+        assert_eq!(tags(1023, None, false), ["privileged"]);
+        assert!(tags(1024, Some("10.0.0.1"), false).is_empty());
+        // Port 0 is an unbound socket, not a privileged one
+        assert!(tags(0, None, false).is_empty());
+        assert_eq!(well_known_service(53), Some("dns"));
+    }
+
+    fn port(port: u16, local_addr: &str, process: Option<&str>, protocol: Protocol, state: &str, pid: Option<i32>) -> PortInfo {
+        PortInfo {
+            local_addr: local_addr.to_string(),
+            remote_addr: String::new(),
+            state: state.to_string(),
+            pid,
+            process: process.map(str::to_string),
+            protocol,
+            port,
+            tags: Vec::new(),
+            user: None,
+        }
+    }
+
+    #[test]
+    fn sorts_by_key_then_port_and_address() {
+        let mut ports = vec![
+            port(8080, "127.0.0.1:8080", Some("nginx"), Protocol::Tcp, "LISTEN", Some(30)),
+            port(53, "0.0.0.0:53", Some("dnsmasq"), Protocol::Udp, "", Some(20)),
+            port(443, "[::]:443", Some("nginx"), Protocol::Tcp, "LISTEN", Some(30)),
+            port(8080, "0.0.0.0:8080", None, Protocol::Tcp, "ESTABLISHED", None),
+            port(22, "0.0.0.0:22", Some("sshd"), Protocol::Tcp, "LISTEN", Some(10)),
+        ];
+        let order = |by: SortBy, ports: &mut Vec<PortInfo>| {
+            by.sort(ports);
+            ports.iter().map(|p| p.local_addr.clone()).collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            order(SortBy::Port, &mut ports),
+            ["0.0.0.0:22", "0.0.0.0:53", "[::]:443", "0.0.0.0:8080", "127.0.0.1:8080"]
+        );
+        // `None` sorts first; ties fall back to port, then address
+        assert_eq!(
+            order(SortBy::Process, &mut ports),
+            ["0.0.0.0:8080", "0.0.0.0:53", "[::]:443", "127.0.0.1:8080", "0.0.0.0:22"]
+        );
+        assert_eq!(
+            order(SortBy::Protocol, &mut ports),
+            ["0.0.0.0:22", "[::]:443", "0.0.0.0:8080", "127.0.0.1:8080", "0.0.0.0:53"]
+        );
+        assert_eq!(
+            order(SortBy::State, &mut ports),
+            ["0.0.0.0:53", "0.0.0.0:8080", "0.0.0.0:22", "[::]:443", "127.0.0.1:8080"]
+        );
+        assert_eq!(
+            order(SortBy::Pid, &mut ports),
+            ["0.0.0.0:8080", "0.0.0.0:22", "0.0.0.0:53", "[::]:443", "127.0.0.1:8080"]
+        );
+
+        // The order doesn't depend on the order going in, so pages stay put between refreshes
+        let mut reversed: Vec<_> = ports.iter().rev().cloned().collect();
+        assert_eq!(order(SortBy::Pid, &mut reversed), order(SortBy::Pid, &mut ports));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn reads_usernames_from_passwd_lines() {
+        let passwd = "root:x:0:0:root:/root:/bin/bash\n\
+                      # a comment\n\
+                      \n\
+                      alice:x:1000:1000:Alice,,,:/home/alice:/bin/zsh\n\
+                      broken:x:notanumber:0::/:/bin/false\n\
+                      nobody:x:65534:65534:nobody:/nonexistent:/usr/sbin/nologin";
+        let users = parse_passwd(passwd);
+        assert_eq!(users.len(), 3);
+        assert_eq!(users[&0], "root");
+        assert_eq!(users[&1000], "alice");
+        assert_eq!(users[&65534], "nobody");
+    }
+}
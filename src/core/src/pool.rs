@@ -0,0 +1,101 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+use crate::reservation::{config_dir, PortRange};
+
+/// An admin-defined block of ports with its own reservation policy
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct Pool {
+    pub name: String,
+    pub range: PortRange,
+    /// How many live reservations a single owner may hold in this pool
+    pub max_per_owner: Option<u32>,
+    /// Lease length applied when a reservation in this pool doesn't ask for one
+    pub default_ttl_secs: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct PoolFile {
+    #[serde(default, rename = "pool")]
+    pools: Vec<Pool>,
+}
+
+/// Pool definitions shared by the TUI and the API unless told otherwise
+pub fn default_pools_path() -> PathBuf {
+    config_dir().join("pools.toml")
+}
+
+/// Read pool definitions from a TOML file of `[[pool]]` tables:
+///
+/// ```toml
+/// [[pool]]
+/// name = "dev-web"
+/// range = "3000-3999"
+/// max_per_owner = 5
+/// default_ttl_secs = 3600
+/// ```
+///
+/// A missing file means no pools. Names must be unique and ranges must not overlap,
+/// so every port belongs to at most one pool.
+pub fn load_pools(path: impl AsRef<Path>) -> Result<Vec<Pool>, String> {
+    let path = path.as_ref();
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let data = fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let file: PoolFile = toml::from_str(&data).map_err(|e| format!("{}: {}", path.display(), e))?;
+    for (i, a) in file.pools.iter().enumerate() {
+        for b in &file.pools[i + 1..] {
+            if a.name == b.name {
+                return Err(format!("Pool '{}' is defined more than once.", a.name));
+            }
+            if a.range.start <= b.range.end && b.range.start <= a.range.end {
+                return Err(format!("Pools '{}' ({}) and '{}' ({}) overlap.", a.name, a.range, b.name, b.range));
+            }
+        }
+    }
+    Ok(file.pools)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(toml: &str) -> Result<Vec<Pool>, String> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("pools.toml");
+        fs::write(&path, toml).unwrap();
+        load_pools(&path)
+    }
+
+    #[test]
+    fn loads_pools_that_dont_overlap() {
+        let pools = load(
+            "[[pool]]\nname = \"web\"\nrange = \"3000-3999\"\nmax_per_owner = 5\ndefault_ttl_secs = 3600\n\n\
+             [[pool]]\nname = \"db\"\nrange = \"4000-4099\"\n",
+        )
+        .unwrap();
+        assert_eq!(pools.len(), 2);
+        assert_eq!((pools[0].range.start, pools[0].range.end), (3000, 3999));
+        assert_eq!((pools[0].max_per_owner, pools[0].default_ttl_secs), (Some(5), Some(3600)));
+        assert_eq!((pools[1].max_per_owner, pools[1].default_ttl_secs), (None, None));
+    }
+
+    #[test]
+    fn missing_file_means_no_pools() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(load_pools(dir.path().join("pools.toml")).unwrap().is_empty());
+    }
+
+    #[test]
+    fn rejects_duplicate_names_and_overlapping_ranges() {
+        let err = load("[[pool]]\nname = \"web\"\nrange = \"3000-3099\"\n[[pool]]\nname = \"web\"\nrange = \"4000-4099\"\n");
+        assert_eq!(err.unwrap_err(), "Pool 'web' is defined more than once.");
+        for (a, b) in [("3000-3099", "3099-3199"), ("3000-3999", "3500"), ("3500-3600", "3000-3999")] {
+            let toml = format!("[[pool]]\nname = \"a\"\nrange = \"{}\"\n[[pool]]\nname = \"b\"\nrange = \"{}\"\n", a, b);
+            assert!(load(&toml).unwrap_err().contains("overlap"), "{} and {}", a, b);
+        }
+        assert!(load("[[pool]]\nname = \"a\"\nrange = \"3000-2000\"\n").is_err());
+    }
+}
@@ -0,0 +1,1230 @@
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::fmt;
+use std::fs;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ToSqlOutput, ValueRef};
+use rusqlite::{params, Connection, OptionalExtension, Row, ToSql, Transaction, TransactionBehavior};
+use serde::{Deserialize, Serialize};
+use crate::net::{addrs_overlap, bound_ports, is_port_in_use, PortInfo, Protocol};
+use crate::audit::{self, AuditEntry, AuditQuery};
+use crate::pool::Pool;
+
+/// Schema migrations, applied in order. `PRAGMA user_version` records how many have run.
+const MIGRATIONS: &[&str] = &[
+    "CREATE TABLE reservations (
+        port       INTEGER NOT NULL,
+        protocol   TEXT    NOT NULL DEFAULT 'TCP',
+        service    TEXT    NOT NULL,
+        owner      TEXT,
+        created_at INTEGER NOT NULL,
+        expires_at INTEGER,
+        PRIMARY KEY (port, protocol)
+    );",
+    // Lease length, so a renewal without an explicit TTL extends by the original amount
+    "ALTER TABLE reservations ADD COLUMN ttl_secs INTEGER;",
+    "ALTER TABLE reservations ADD COLUMN pool TEXT;",
+    // Key on (protocol, port, bind address) so TCP and UDP, or different addresses,
+    // can be reserved independently. '' in bind_addr means every address.
+    "CREATE TABLE reservations_v4 (
+        protocol   TEXT    NOT NULL,
+        port       INTEGER NOT NULL,
+        bind_addr  TEXT    NOT NULL DEFAULT '',
+        service    TEXT    NOT NULL,
+        owner      TEXT,
+        created_at INTEGER NOT NULL,
+        expires_at INTEGER,
+        ttl_secs   INTEGER,
+        pool       TEXT,
+        PRIMARY KEY (protocol, port, bind_addr)
+    );
+    INSERT INTO reservations_v4 (protocol, port, service, owner, created_at, expires_at, ttl_secs, pool)
+        SELECT protocol, port, service, owner, created_at, expires_at, ttl_secs, pool FROM reservations;
+    DROP TABLE reservations;
+    ALTER TABLE reservations_v4 RENAME TO reservations;",
+    "ALTER TABLE reservations ADD COLUMN notes TEXT;",
+    // Append-only: the triggers refuse to change or drop what was recorded
+    "CREATE TABLE audit (
+        id        INTEGER PRIMARY KEY AUTOINCREMENT,
+        timestamp INTEGER NOT NULL,
+        actor     TEXT    NOT NULL,
+        action    TEXT    NOT NULL,
+        protocol  TEXT,
+        port      INTEGER,
+        pid       INTEGER,
+        result    TEXT    NOT NULL,
+        detail    TEXT
+    );
+    CREATE INDEX audit_port ON audit (port);
+    CREATE TRIGGER audit_no_update BEFORE UPDATE ON audit
+        BEGIN SELECT RAISE(ABORT, 'the audit log is append-only'); END;
+    CREATE TRIGGER audit_no_delete BEFORE DELETE ON audit
+        BEGIN SELECT RAISE(ABORT, 'the audit log is append-only'); END;",
+];
+
+/// Directory holding state shared by all frontends:
+/// `$XDG_STATE_HOME/port-manager`, or `~/.local/state/port-manager` when unset.
+pub fn state_dir() -> PathBuf {
+    let base = match env::var_os("XDG_STATE_HOME").filter(|v| !v.is_empty()) {
+        Some(dir) => PathBuf::from(dir),
+        None => match env::var_os("HOME") {
+            Some(home) => Path::new(&home).join(".local").join("state"),
+            None => PathBuf::from("."),
+        },
+    };
+    base.join("port-manager")
+}
+
+/// Directory holding admin-edited configuration such as `pools.toml`:
+/// `$XDG_CONFIG_HOME/port-manager`, or `~/.config/port-manager` when unset.
+pub fn config_dir() -> PathBuf {
+    let base = match env::var_os("XDG_CONFIG_HOME").filter(|v| !v.is_empty()) {
+        Some(dir) => PathBuf::from(dir),
+        None => match env::var_os("HOME") {
+            Some(home) => Path::new(&home).join(".config"),
+            None => PathBuf::from("."),
+        },
+    };
+    base.join("port-manager")
+}
+
+/// Reservation database used by the TUI and the API unless told otherwise
+pub fn default_store_path() -> PathBuf {
+    state_dir().join("reservations.db")
+}
+
+/// Unix sockets where clients look for an API daemon, in order: a per-user one in
+/// `$XDG_RUNTIME_DIR`, then a shared one in `/run`
+pub fn default_socket_paths() -> Vec<PathBuf> {
+    let mut paths = Vec::new();
+    if let Some(dir) = env::var_os("XDG_RUNTIME_DIR").filter(|v| !v.is_empty()) {
+        paths.push(Path::new(&dir).join("port-manager.sock"));
+    }
+    paths.push(PathBuf::from("/run/port-manager.sock"));
+    paths
+}
+
+/// Seconds since the Unix epoch
+pub fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or(Duration::ZERO).as_secs()
+}
+
+/// Owner recorded for reservations made by Unix user `uid`, which is how a daemon names
+/// callers on its socket
+pub fn uid_owner(uid: u32) -> String {
+    format!("uid:{}", uid)
+}
+
+/// Owner of reservations made without a daemon, matching what a daemon would record
+/// for the same user so either may release them later
+#[cfg(unix)]
+pub fn local_owner() -> String {
+    uid_owner(nix::unistd::geteuid().as_raw())
+}
+
+/// Inclusive port range, written `3000-3999` (or a single port, `8080`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct PortRange {
+    pub start: u16,
+    pub end: u16,
+}
+
+#[cfg(feature = "openapi")]
+impl utoipa::PartialSchema for PortRange {
+    fn schema() -> utoipa::openapi::RefOr<utoipa::openapi::schema::Schema> {
+        utoipa::openapi::ObjectBuilder::new()
+            .schema_type(utoipa::openapi::Type::String)
+            .pattern(Some("^[0-9]+(-[0-9]+)?$"))
+            .examples(["3000-3999"])
+            .description(Some("Inclusive port range, or a single port"))
+            .into()
+    }
+}
+
+#[cfg(feature = "openapi")]
+impl utoipa::ToSchema for PortRange {}
+
+impl PortRange {
+    pub fn new(start: u16, end: u16) -> Result<Self, String> {
+        if start == 0 {
+            return Err(format!("Invalid port range {}-{}: ports start at 1.", start, end));
+        }
+        if start > end {
+            return Err(format!("Invalid port range {}-{}: start is after end.", start, end));
+        }
+        Ok(Self { start, end })
+    }
+
+    pub fn contains(&self, port: u16) -> bool {
+        self.start <= port && port <= self.end
+    }
+}
+
+impl fmt::Display for PortRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.start, self.end)
+    }
+}
+
+impl TryFrom<String> for PortRange {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<PortRange> for String {
+    fn from(range: PortRange) -> Self {
+        range.to_string()
+    }
+}
+
+impl FromStr for PortRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse = |p: &str| p.trim().parse::<u16>().map_err(|_| format!("Invalid port range '{}'.", s));
+        match s.split_once('-') {
+            Some((start, end)) => Self::new(parse(start)?, parse(end)?),
+            None => parse(s).and_then(|port| Self::new(port, port)),
+        }
+    }
+}
+
+impl ToSql for Protocol {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.as_str()))
+    }
+}
+
+impl FromSql for Protocol {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        value.as_str()?.parse().map_err(|e: String| FromSqlError::Other(e.into()))
+    }
+}
+
+/// Stored form of a bind address: '' for "every address"
+fn addr_to_sql(addr: Option<IpAddr>) -> String {
+    addr.map(|a| a.to_string()).unwrap_or_default()
+}
+
+/// Why a reservation operation failed. Each kind maps to a stable `code()` that API
+/// clients can match on; `Display` gives the human-readable message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReservationError {
+    /// A live reservation already covers the port
+    AlreadyReserved(String),
+    /// A socket is bound to the port
+    InUse(String),
+    /// No live reservation matches
+    NotFound(String),
+    /// The caller's check refused the operation
+    Forbidden(String),
+    /// The owner already holds as many reservations as the pool allows
+    LimitReached(String),
+    /// Every port in the range is bound or reserved
+    NoFreePort(String),
+    /// The request doesn't make sense, e.g. an unknown pool
+    Invalid(String),
+    /// The database failed
+    Storage(String),
+}
+
+impl ReservationError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            ReservationError::AlreadyReserved(_) => "ALREADY_RESERVED",
+            ReservationError::InUse(_) => "IN_USE",
+            ReservationError::NotFound(_) => "NOT_FOUND",
+            ReservationError::Forbidden(_) => "FORBIDDEN",
+            ReservationError::LimitReached(_) => "LIMIT_REACHED",
+            ReservationError::NoFreePort(_) => "NO_FREE_PORT",
+            ReservationError::Invalid(_) => "INVALID_REQUEST",
+            ReservationError::Storage(_) => "STORAGE_ERROR",
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        match self {
+            ReservationError::AlreadyReserved(m)
+            | ReservationError::InUse(m)
+            | ReservationError::NotFound(m)
+            | ReservationError::Forbidden(m)
+            | ReservationError::LimitReached(m)
+            | ReservationError::NoFreePort(m)
+            | ReservationError::Invalid(m)
+            | ReservationError::Storage(m) => m,
+        }
+    }
+}
+
+impl fmt::Display for ReservationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.message())
+    }
+}
+
+impl std::error::Error for ReservationError {}
+
+impl From<rusqlite::Error> for ReservationError {
+    fn from(e: rusqlite::Error) -> Self {
+        ReservationError::Storage(e.to_string())
+    }
+}
+
+impl From<ReservationError> for String {
+    fn from(e: ReservationError) -> Self {
+        e.to_string()
+    }
+}
+
+/// What a reservation is held on. Two keys conflict when protocol and port match and
+/// their bind addresses overlap (see `net::addrs_overlap`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ReservationKey {
+    pub protocol: Protocol,
+    pub port: u16,
+    /// `None` reserves the port on every address
+    pub bind_addr: Option<IpAddr>,
+}
+
+impl ReservationKey {
+    /// TCP on every address, the default for callers that only know a port number
+    pub fn tcp(port: u16) -> Self {
+        Self { protocol: Protocol::Tcp, port, bind_addr: None }
+    }
+}
+
+impl fmt::Display for ReservationKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.bind_addr {
+            Some(IpAddr::V6(addr)) => write!(f, "{} [{}]:{}", self.protocol, addr, self.port),
+            Some(addr) => write!(f, "{} {}:{}", self.protocol, addr, self.port),
+            None => write!(f, "{} port {}", self.protocol, self.port),
+        }
+    }
+}
+
+/// A single row of the reservation store
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct Reservation {
+    pub port: u16,
+    pub protocol: Protocol,
+    /// `null` when the reservation covers every address
+    #[cfg_attr(feature = "openapi", schema(value_type = Option<String>, example = "127.0.0.1"))]
+    pub bind_addr: Option<IpAddr>,
+    pub service: String,
+    pub owner: Option<String>,
+    pub created_at: u64,
+    pub expires_at: Option<u64>,
+    pub ttl_secs: Option<u64>,
+    pub pool: Option<String>,
+    /// Free-form text from whoever holds the port
+    pub notes: Option<String>,
+}
+
+impl Reservation {
+    fn from_row(row: &Row) -> rusqlite::Result<Self> {
+        let bind_addr: String = row.get(2)?;
+        let bind_addr = match bind_addr.as_str() {
+            "" => None,
+            addr => Some(addr.parse().map_err(|e| {
+                rusqlite::Error::FromSqlConversionFailure(2, rusqlite::types::Type::Text, Box::new(e))
+            })?),
+        };
+        Ok(Self {
+            port: row.get(0)?,
+            protocol: row.get(1)?,
+            bind_addr,
+            service: row.get(3)?,
+            owner: row.get(4)?,
+            created_at: row.get(5)?,
+            expires_at: row.get(6)?,
+            ttl_secs: row.get(7)?,
+            pool: row.get(8)?,
+            notes: row.get(9)?,
+        })
+    }
+
+    pub fn key(&self) -> ReservationKey {
+        ReservationKey { protocol: self.protocol, port: self.port, bind_addr: self.bind_addr }
+    }
+
+    /// Time left on the lease, `None` for reservations that never expire
+    pub fn remaining(&self) -> Option<Duration> {
+        self.expires_at.map(|at| Duration::from_secs(at.saturating_sub(unix_now())))
+    }
+}
+
+const SELECT_COLUMNS: &str =
+    "SELECT port, protocol, bind_addr, service, owner, created_at, expires_at, ttl_secs, pool, notes FROM reservations";
+/// Matches rows whose lease hasn't run out; expired rows linger until the next reap
+const LIVE: &str = "(expires_at IS NULL OR expires_at > ?1)";
+/// Matches the row for exactly one key, bound as ?2 protocol, ?3 port, ?4 bind address
+const EXACT_KEY: &str = "protocol = ?2 AND port = ?3 AND bind_addr = ?4";
+
+/// Who wants a port and on what terms; shared by `reserve` and `allocate`
+#[derive(Debug, Clone)]
+pub struct ReservationRequest {
+    pub service: String,
+    pub owner: Option<String>,
+    /// Pool to reserve from. Reservations without one still fall under the pool whose
+    /// range contains the port, if any.
+    pub pool: Option<String>,
+    /// Lease length; `None` uses the pool's default TTL, or no expiry outside a pool
+    pub ttl: Option<Duration>,
+    pub protocol: Protocol,
+    /// `None` reserves the port on every address
+    pub bind_addr: Option<IpAddr>,
+    pub notes: Option<String>,
+}
+
+impl ReservationRequest {
+    pub fn new(service: impl Into<String>) -> Self {
+        Self {
+            service: service.into(),
+            owner: None,
+            pool: None,
+            ttl: None,
+            protocol: Protocol::Tcp,
+            bind_addr: None,
+            notes: None,
+        }
+    }
+}
+
+/// Changes to make to a live reservation; `None` leaves a field as it is
+#[derive(Debug, Clone, Default)]
+pub struct ReservationUpdate {
+    pub service: Option<String>,
+    /// Restart the lease with this length, turning a permanent reservation into a lease
+    pub ttl: Option<Duration>,
+    /// Replace the notes; an empty string clears them
+    pub notes: Option<String>,
+}
+
+/// Struct to manage port reservations, backed by SQLite
+#[derive(Debug)]
+pub struct PortReservationManager {
+    conn: Mutex<Connection>,
+    pools: Vec<Pool>,
+}
+
+impl Default for PortReservationManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PortReservationManager {
+    /// In-memory store, for callers that don't need reservations to outlive the process
+    pub fn new() -> Self {
+        let conn = Connection::open_in_memory().expect("in-memory SQLite database");
+        Self::from_connection(conn).expect("in-memory SQLite migrations")
+    }
+
+    /// Open (creating if needed) the database at `path` and bring its schema up to date.
+    /// A `reservations.json` left next to it by older versions is imported once.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, String> {
+        let path = path.as_ref();
+        if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+            fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+        }
+        let conn = Connection::open(path).map_err(|e| e.to_string())?;
+        conn.busy_timeout(Duration::from_secs(5)).map_err(|e| e.to_string())?;
+        conn.pragma_update(None, "journal_mode", "WAL").map_err(|e| e.to_string())?;
+        let manager = Self::from_connection(conn)?;
+        manager.import_legacy_json(path.with_file_name("reservations.json"))?;
+        Ok(manager)
+    }
+
+    fn from_connection(mut conn: Connection) -> Result<Self, String> {
+        migrate(&mut conn, MIGRATIONS).map_err(|e| e.to_string())?;
+        Ok(Self { conn: Mutex::new(conn), pools: Vec::new() })
+    }
+
+    /// Govern reservations by these pools (see `pool::load_pools`)
+    pub fn with_pools(mut self, pools: Vec<Pool>) -> Self {
+        self.pools = pools;
+        self
+    }
+
+    pub fn pools(&self) -> &[Pool] {
+        &self.pools
+    }
+
+    pub fn pool(&self, name: &str) -> Option<&Pool> {
+        self.pools.iter().find(|p| p.name == name)
+    }
+
+    /// Number of live reservations held in a pool
+    pub fn pool_usage(&self, name: &str) -> usize {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row(
+            &format!("SELECT COUNT(*) FROM reservations WHERE pool = ?2 AND {}", LIVE),
+            params![unix_now(), name],
+            |r| r.get(0),
+        ).unwrap_or(0)
+    }
+
+    /// Reserve a TCP port on every address for a service
+    pub fn reserve_port(&self, port: u16, service: String) -> Result<(), ReservationError> {
+        self.reserve(port, ReservationRequest::new(service)).map(|_| ())
+    }
+
+    /// Reserve a specific port. With a TTL (requested, or the pool's default) the
+    /// reservation is a lease that lapses unless renewed; without one it lasts until released.
+    pub fn reserve(&self, port: u16, req: ReservationRequest) -> Result<Reservation, ReservationError> {
+        let pool = match req.pool.as_deref() {
+            Some(name) => {
+                let pool = self.pool(name).ok_or_else(|| unknown_pool(name))?;
+                if !pool.range.contains(port) {
+                    return Err(ReservationError::Invalid(format!(
+                        "Port {} is outside pool '{}' ({}).",
+                        port, pool.name, pool.range
+                    )));
+                }
+                Some(pool)
+            }
+            None => self.pools.iter().find(|p| p.range.contains(port)),
+        };
+        let key = ReservationKey { protocol: req.protocol, port, bind_addr: req.bind_addr };
+        let mut conn = self.conn.lock().unwrap();
+        // IMMEDIATE takes the write lock up front, so two processes can't both pass the check
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let now = unix_now();
+        if let Some(existing) = live_on_port(&tx, req.protocol, port, now)?
+            .into_iter()
+            .find(|r| addrs_overlap(r.bind_addr, req.bind_addr))
+        {
+            return Err(ReservationError::AlreadyReserved(if existing.bind_addr == req.bind_addr {
+                format!("Port {} is already reserved.", port)
+            } else {
+                format!("Port {} is already reserved as {}.", port, existing.key())
+            }));
+        }
+        if is_port_in_use(req.protocol, port, req.bind_addr) {
+            return Err(ReservationError::InUse(format!("Port {} is currently in use by another process.", port)));
+        }
+        let reservation = insert_reservation(&tx, key, req, pool, now)?;
+        tx.commit()?;
+        Ok(reservation)
+    }
+
+    /// Reserve the first port that is neither bound nor reserved, returning it. The port
+    /// comes from `range`, from the requested pool, or from `range` within that pool.
+    /// The pick and the insert happen in one write transaction, so concurrent callers
+    /// (in this process or another) always get different ports.
+    pub fn allocate(&self, range: Option<PortRange>, req: ReservationRequest) -> Result<Reservation, ReservationError> {
+        let pool = match req.pool.as_deref() {
+            Some(name) => Some(self.pool(name).ok_or_else(|| unknown_pool(name))?),
+            None => None,
+        };
+        let range = match (range, pool) {
+            (Some(range), Some(pool)) if !(pool.range.contains(range.start) && pool.range.contains(range.end)) => {
+                return Err(ReservationError::Invalid(format!(
+                    "Range {} is outside pool '{}' ({}).",
+                    range, pool.name, pool.range
+                )));
+            }
+            (Some(range), _) => range,
+            (None, Some(pool)) => pool.range,
+            (None, None) => return Err(ReservationError::Invalid("Allocation needs a port range or a pool.".to_string())),
+        };
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let now = unix_now();
+        let taken: HashSet<u16> = {
+            let mut stmt = tx.prepare(&format!(
+                "{} WHERE protocol = ?2 AND port BETWEEN ?3 AND ?4 AND {}",
+                SELECT_COLUMNS, LIVE
+            ))?;
+            let rows = stmt.query_map(params![now, req.protocol, range.start, range.end], Reservation::from_row)?;
+            rows.collect::<rusqlite::Result<Vec<_>>>()?
+                .into_iter()
+                .filter(|r| addrs_overlap(r.bind_addr, req.bind_addr))
+                .map(|r| r.port)
+                .collect()
+        };
+        let bound = bound_ports(req.protocol, req.bind_addr);
+        let port = (range.start..=range.end)
+            .find(|p| !taken.contains(p) && !bound.contains(p))
+            .ok_or_else(|| ReservationError::NoFreePort(format!("No free port in range {}.", range)))?;
+        // Without an explicit pool, the chosen port may still fall inside one
+        let pool = pool.or_else(|| self.pools.iter().find(|p| p.range.contains(port)));
+        let key = ReservationKey { protocol: req.protocol, port, bind_addr: req.bind_addr };
+        let reservation = insert_reservation(&tx, key, req, pool, now)?;
+        tx.commit()?;
+        Ok(reservation)
+    }
+
+    /// Extend a lease by `ttl`, or by its original length when `ttl` is `None`.
+    /// Giving a `ttl` to a permanent reservation turns it into a lease.
+    pub fn renew_lease(&self, key: ReservationKey, ttl: Option<Duration>) -> Result<Reservation, ReservationError> {
+        self.renew_lease_if(key, ttl, |_| Ok(()))
+    }
+
+    /// `renew_lease`, but only if `allow` accepts the current reservation. The check runs
+    /// in the same transaction as the update, so the holder can't change in between.
+    pub fn renew_lease_if(
+        &self,
+        key: ReservationKey,
+        ttl: Option<Duration>,
+        allow: impl FnOnce(&Reservation) -> Result<(), ReservationError>,
+    ) -> Result<Reservation, ReservationError> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let now = unix_now();
+        let current = live_exact(&tx, key, now)?;
+        allow(&current)?;
+        let ttl_secs = ttl
+            .map(|t| t.as_secs())
+            .or(current.ttl_secs)
+            .ok_or_else(|| ReservationError::Invalid(format!("Port {} has no lease to renew.", key.port)))?;
+        tx.execute(
+            &format!("UPDATE reservations SET expires_at = ?5, ttl_secs = ?6 WHERE {} AND {}", EXACT_KEY, LIVE),
+            params![now, key.protocol, key.port, addr_to_sql(key.bind_addr), now + ttl_secs, ttl_secs],
+        )?;
+        tx.commit()?;
+        Ok(Reservation { expires_at: Some(now + ttl_secs), ttl_secs: Some(ttl_secs), ..current })
+    }
+
+    /// Change the service name, lease or notes of the reservation on exactly `key`, if
+    /// `allow` accepts it. The check runs in the same transaction as the update.
+    pub fn update_if(
+        &self,
+        key: ReservationKey,
+        update: ReservationUpdate,
+        allow: impl FnOnce(&Reservation) -> Result<(), ReservationError>,
+    ) -> Result<Reservation, ReservationError> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let now = unix_now();
+        let current = live_exact(&tx, key, now)?;
+        allow(&current)?;
+        let (expires_at, ttl_secs) = match update.ttl {
+            Some(ttl) => (Some(now + ttl.as_secs()), Some(ttl.as_secs())),
+            None => (current.expires_at, current.ttl_secs),
+        };
+        let notes = match update.notes {
+            Some(notes) if notes.is_empty() => None,
+            Some(notes) => Some(notes),
+            None => current.notes.clone(),
+        };
+        let service = update.service.unwrap_or_else(|| current.service.clone());
+        let updated = Reservation { service, expires_at, ttl_secs, notes, ..current };
+        tx.execute(
+            &format!(
+                "UPDATE reservations SET service = ?5, expires_at = ?6, ttl_secs = ?7, notes = ?8 WHERE {} AND {}",
+                EXACT_KEY, LIVE
+            ),
+            params![
+                now,
+                key.protocol,
+                key.port,
+                addr_to_sql(key.bind_addr),
+                updated.service,
+                updated.expires_at,
+                updated.ttl_secs,
+                updated.notes,
+            ],
+        )?;
+        tx.commit()?;
+        Ok(updated)
+    }
+
+    /// Delete every lease that has run out, returning what was reclaimed
+    pub fn reap_expired(&self) -> Result<Vec<Reservation>, String> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate).map_err(|e| e.to_string())?;
+        let now = unix_now();
+        let expired = {
+            let mut stmt = tx
+                .prepare(&format!("{} WHERE NOT {}", SELECT_COLUMNS, LIVE))
+                .map_err(|e| e.to_string())?;
+            let rows = stmt.query_map([now], Reservation::from_row).map_err(|e| e.to_string())?;
+            rows.collect::<rusqlite::Result<Vec<_>>>().map_err(|e| e.to_string())?
+        };
+        tx.execute(&format!("DELETE FROM reservations WHERE NOT {}", LIVE), [now])
+            .map_err(|e| e.to_string())?;
+        tx.commit().map_err(|e| e.to_string())?;
+        Ok(expired)
+    }
+
+    /// Release the reservation held on exactly `key`
+    pub fn release(&self, key: ReservationKey) -> Result<Reservation, ReservationError> {
+        self.release_if(key, |_| Ok(()))
+    }
+
+    /// `release`, but only if `allow` accepts the current reservation. The check runs
+    /// in the same transaction as the delete, so the holder can't change in between.
+    pub fn release_if(
+        &self,
+        key: ReservationKey,
+        allow: impl FnOnce(&Reservation) -> Result<(), ReservationError>,
+    ) -> Result<Reservation, ReservationError> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let now = unix_now();
+        let current = live_exact(&tx, key, now)?;
+        allow(&current)?;
+        tx.execute(
+            &format!("DELETE FROM reservations WHERE {} AND {}", EXACT_KEY, LIVE),
+            params![now, key.protocol, key.port, addr_to_sql(key.bind_addr)],
+        )?;
+        tx.commit()?;
+        Ok(current)
+    }
+
+    /// Release `held`, but only while it is still the reservation on its key: same owner
+    /// and creation time. A lease that lapsed and was taken by someone else stays theirs.
+    pub fn release_exact(&self, held: &Reservation) -> Result<Reservation, ReservationError> {
+        self.release_if(held.key(), |current| {
+            if current.owner == held.owner && current.created_at == held.created_at {
+                Ok(())
+            } else {
+                Err(replaced(held))
+            }
+        })
+    }
+
+    /// Release a TCP reservation held on every address
+    pub fn release_port(&self, port: u16) -> Result<(), ReservationError> {
+        self.release(ReservationKey::tcp(port)).map(|_| ())
+    }
+
+    /// Check if a port is reserved under any protocol or address
+    pub fn is_reserved(&self, port: u16) -> bool {
+        !self.reservations_on_port(port).is_empty()
+    }
+
+    /// Get the service name for a reserved port
+    pub fn get_service(&self, port: u16) -> Option<String> {
+        self.reservations_on_port(port).into_iter().next().map(|r| r.service)
+    }
+
+    /// Get the reservation held on exactly `key`
+    pub fn get_reservation(&self, key: ReservationKey) -> Option<Reservation> {
+        let conn = self.conn.lock().unwrap();
+        live_exact(&conn, key, unix_now()).ok()
+    }
+
+    /// The reservation a socket on `protocol`/`port`/`addr` would fall under, if any
+    pub fn find_reservation(&self, protocol: Protocol, port: u16, addr: Option<IpAddr>) -> Option<Reservation> {
+        let conn = self.conn.lock().unwrap();
+        live_on_port(&conn, protocol, port, unix_now())
+            .ok()?
+            .into_iter()
+            .find(|r| addrs_overlap(r.bind_addr, addr))
+    }
+
+    /// Add the `reserved` tag to every socket a live reservation covers
+    pub fn tag_reserved(&self, ports: &mut [PortInfo]) {
+        let reservations = self.get_all_reservations();
+        for p in ports.iter_mut() {
+            let ip = p.local_ip();
+            if reservations.iter().any(|r| r.protocol == p.protocol && r.port == p.port && addrs_overlap(r.bind_addr, ip)) {
+                p.tags.push("reserved".to_string());
+            }
+        }
+    }
+
+    /// Append `entry` to the audit log and return its id
+    pub fn record_audit(&self, entry: &AuditEntry) -> Result<i64, ReservationError> {
+        let conn = self.conn.lock().unwrap();
+        Ok(audit::insert(&conn, entry)?)
+    }
+
+    /// Audit entries matching `query`, newest first
+    pub fn audit_log(&self, query: &AuditQuery) -> Result<Vec<AuditEntry>, ReservationError> {
+        let conn = self.conn.lock().unwrap();
+        Ok(audit::query(&conn, query)?)
+    }
+
+    /// Every live reservation on `port`, whatever the protocol or address
+    pub fn reservations_on_port(&self, port: u16) -> Vec<Reservation> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = match conn.prepare(&format!("{} WHERE port = ?2 AND {} ORDER BY protocol, bind_addr", SELECT_COLUMNS, LIVE)) {
+            Ok(stmt) => stmt,
+            Err(_) => return Vec::new(),
+        };
+        stmt.query_map(params![unix_now(), port], Reservation::from_row)
+            .map(|rows| rows.flatten().collect())
+            .unwrap_or_default()
+    }
+
+    pub fn get_all_reservations(&self) -> Vec<Reservation> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = match conn.prepare(&format!("{} WHERE {} ORDER BY port, protocol, bind_addr", SELECT_COLUMNS, LIVE)) {
+            Ok(stmt) => stmt,
+            Err(_) => return Vec::new(),
+        };
+        stmt.query_map([unix_now()], Reservation::from_row)
+            .map(|rows| rows.flatten().collect())
+            .unwrap_or_default()
+    }
+
+    /// One-time import of a `reservations.json` (`{"port": "service"}`) written by older
+    /// versions as TCP reservations on every address. Ports already in the database are
+    /// left alone, and the file is renamed to `reservations.json.imported` so it isn't
+    /// picked up again. Returns the number imported.
+    pub fn import_legacy_json(&self, path: impl AsRef<Path>) -> Result<usize, String> {
+        let path = path.as_ref();
+        if !path.exists() {
+            return Ok(0);
+        }
+        let data = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        let legacy: HashMap<u16, String> = serde_json::from_str(&data).map_err(|e| e.to_string())?;
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate).map_err(|e| e.to_string())?;
+        let mut imported = 0;
+        let now = unix_now();
+        for (port, service) in legacy {
+            imported += tx.execute(
+                "INSERT INTO reservations (protocol, port, bind_addr, service, owner, created_at)
+                 SELECT 'TCP', ?1, '', ?2, NULL, ?3
+                 WHERE NOT EXISTS (SELECT 1 FROM reservations WHERE protocol = 'TCP' AND port = ?1)",
+                params![port, service, now],
+            ).map_err(|e| e.to_string())?;
+        }
+        tx.commit().map_err(|e| e.to_string())?;
+        let mut done = path.as_os_str().to_owned();
+        done.push(".imported");
+        fs::rename(path, done).map_err(|e| e.to_string())?;
+        Ok(imported)
+    }
+}
+
+/// `held` has been released or has lapsed, and the key now holds another reservation
+fn replaced(held: &Reservation) -> ReservationError {
+    ReservationError::NotFound(format!("{} is no longer held by that reservation.", held.key()))
+}
+
+fn unknown_pool(name: &str) -> ReservationError {
+    ReservationError::Invalid(format!("Unknown pool '{}'.", name))
+}
+
+/// The live reservation held on exactly `key`
+fn live_exact(conn: &Connection, key: ReservationKey, now: u64) -> Result<Reservation, ReservationError> {
+    conn.query_row(
+        &format!("{} WHERE {} AND {}", SELECT_COLUMNS, EXACT_KEY, LIVE),
+        params![now, key.protocol, key.port, addr_to_sql(key.bind_addr)],
+        Reservation::from_row,
+    )
+    .optional()?
+    .ok_or_else(|| ReservationError::NotFound(format!("Port {} was not reserved.", key.port)))
+}
+
+/// Live reservations on `protocol`/`port`, on any address
+fn live_on_port(conn: &Connection, protocol: Protocol, port: u16, now: u64) -> rusqlite::Result<Vec<Reservation>> {
+    let mut stmt = conn.prepare(&format!("{} WHERE protocol = ?2 AND port = ?3 AND {}", SELECT_COLUMNS, LIVE))?;
+    let rows = stmt.query_map(params![now, protocol, port], Reservation::from_row)?;
+    rows.collect()
+}
+
+/// Apply `pool`'s policy to `req` and insert the row. The caller has already checked
+/// that `key` is free and commits the transaction.
+fn insert_reservation(
+    tx: &Transaction,
+    key: ReservationKey,
+    req: ReservationRequest,
+    pool: Option<&Pool>,
+    now: u64,
+) -> Result<Reservation, ReservationError> {
+    if let Some((pool, max)) = pool.and_then(|p| p.max_per_owner.map(|max| (p, max))) {
+        let held: u32 = tx.query_row(
+            &format!("SELECT COUNT(*) FROM reservations WHERE pool = ?2 AND owner IS ?3 AND {}", LIVE),
+            params![now, pool.name, req.owner],
+            |r| r.get(0),
+        )?;
+        if held >= max {
+            return Err(ReservationError::LimitReached(format!(
+                "{} already holds {} of {} allowed reservations in pool '{}'.",
+                req.owner.as_deref().unwrap_or("Anonymous owner"), held, max, pool.name
+            )));
+        }
+    }
+    // An expired lease that hasn't been reaped yet doesn't block a new reservation
+    tx.execute(
+        &format!("DELETE FROM reservations WHERE {} AND NOT {}", EXACT_KEY, LIVE),
+        params![now, key.protocol, key.port, addr_to_sql(key.bind_addr)],
+    )?;
+    let ttl_secs = req.ttl.map(|t| t.as_secs()).or(pool.and_then(|p| p.default_ttl_secs));
+    let reservation = Reservation {
+        port: key.port,
+        protocol: key.protocol,
+        bind_addr: key.bind_addr,
+        service: req.service,
+        owner: req.owner,
+        created_at: now,
+        expires_at: ttl_secs.map(|t| now + t),
+        ttl_secs,
+        pool: pool.map(|p| p.name.clone()),
+        notes: req.notes,
+    };
+    tx.execute(
+        "INSERT INTO reservations (protocol, port, bind_addr, service, owner, created_at, expires_at, ttl_secs, pool, notes)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+        params![
+            reservation.protocol,
+            reservation.port,
+            addr_to_sql(reservation.bind_addr),
+            reservation.service,
+            reservation.owner,
+            reservation.created_at,
+            reservation.expires_at,
+            reservation.ttl_secs,
+            reservation.pool,
+            reservation.notes,
+        ],
+    )?;
+    Ok(reservation)
+}
+
+/// Bring a database up to date by running whichever of `migrations` haven't run yet,
+/// tracked in `PRAGMA user_version`
+pub fn migrate(conn: &mut Connection, migrations: &[&str]) -> rusqlite::Result<()> {
+    // Read the version inside the write transaction so two processes opening a fresh
+    // database don't both run the same migration.
+    let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+    let version: usize = tx.pragma_query_value(None, "user_version", |r| r.get(0))?;
+    for (i, migration) in migrations.iter().enumerate().skip(version) {
+        tx.execute_batch(migration)?;
+        tx.pragma_update(None, "user_version", i + 1)?;
+    }
+    tx.commit()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::sync::{Arc, Barrier};
+    use std::thread;
+
+    /// A TCP port nothing was listening on a moment ago
+    fn free_port() -> u16 {
+        TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port()
+    }
+
+    fn user_version(path: &Path) -> usize {
+        let conn = Connection::open(path).unwrap();
+        conn.pragma_query_value(None, "user_version", |r| r.get(0)).unwrap()
+    }
+
+    /// `len` consecutive ports below the ephemeral range with nothing bound to them
+    fn free_range(len: u16) -> PortRange {
+        let bound = bound_ports(Protocol::Tcp, None);
+        (20000..30000)
+            .step_by(len as usize)
+            .map(|start| PortRange::new(start, start + len - 1).unwrap())
+            .find(|range| (range.start..=range.end).all(|p| !bound.contains(&p)))
+            .expect("a free port range")
+    }
+
+    #[test]
+    fn concurrent_reserves_on_one_database_have_one_winner() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("reservations.db");
+        // Separate connections lock each other out exactly like separate processes do
+        let managers = [
+            Arc::new(PortReservationManager::open(&path).unwrap()),
+            Arc::new(PortReservationManager::open(&path).unwrap()),
+        ];
+        let port = free_port();
+        let barrier = Arc::new(Barrier::new(8));
+        let handles: Vec<_> = (0..8)
+            .map(|i| {
+                let manager = managers[i % 2].clone();
+                let barrier = barrier.clone();
+                thread::spawn(move || {
+                    barrier.wait();
+                    manager.reserve(port, ReservationRequest::new(format!("service-{}", i)))
+                })
+            })
+            .collect();
+        let results: Vec<_> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+        assert_eq!(results.iter().filter(|r| r.is_ok()).count(), 1, "{:?}", results);
+        for result in &results {
+            if let Err(e) = result {
+                assert!(matches!(e, ReservationError::AlreadyReserved(_)), "{:?}", e);
+            }
+        }
+        assert_eq!(managers[0].get_all_reservations().len(), 1);
+        assert_eq!(managers[1].get_all_reservations().len(), 1);
+    }
+
+    #[test]
+    fn protocols_and_addresses_reserve_separately() {
+        let manager = PortReservationManager::new();
+        let port = free_port();
+        let on = |protocol, bind_addr: Option<&str>| ReservationRequest {
+            protocol,
+            bind_addr: bind_addr.map(|a| a.parse().unwrap()),
+            ..ReservationRequest::new("dns")
+        };
+        manager.reserve(port, on(Protocol::Tcp, None)).unwrap();
+        manager.reserve(port, on(Protocol::Udp, None)).unwrap();
+        let err = manager.reserve(port, on(Protocol::Udp, Some("127.0.0.1"))).unwrap_err();
+        assert!(matches!(err, ReservationError::AlreadyReserved(_)), "{:?}", err);
+
+        let port = free_port();
+        manager.reserve(port, on(Protocol::Tcp, Some("0.0.0.0"))).unwrap();
+        let err = manager.reserve(port, on(Protocol::Tcp, Some("127.0.0.1"))).unwrap_err();
+        assert!(matches!(err, ReservationError::AlreadyReserved(_)), "{:?}", err);
+        manager.reserve(port, on(Protocol::Tcp, Some("::1"))).unwrap();
+        assert_eq!(manager.get_all_reservations().len(), 4);
+    }
+
+    /// Move the lease on `port` to end at `expires_at`, as if time had passed
+    fn set_expiry(manager: &PortReservationManager, port: u16, expires_at: u64) {
+        let conn = manager.conn.lock().unwrap();
+        conn.execute("UPDATE reservations SET expires_at = ?1 WHERE port = ?2", params![expires_at, port]).unwrap();
+    }
+
+    fn tcp(port: u16) -> ReservationKey {
+        ReservationKey { protocol: Protocol::Tcp, port, bind_addr: None }
+    }
+
+    fn lease(ttl_secs: u64) -> ReservationRequest {
+        ReservationRequest { ttl: Some(Duration::from_secs(ttl_secs)), ..ReservationRequest::new("web") }
+    }
+
+    #[test]
+    fn renewing_restarts_the_lease_at_its_original_length() {
+        let manager = PortReservationManager::new();
+        let port = free_port();
+        manager.reserve(port, lease(600)).unwrap();
+        set_expiry(&manager, port, unix_now() + 5);
+        let renewed = manager.renew_lease(tcp(port), None).unwrap();
+        assert_eq!(renewed.ttl_secs, Some(600));
+        let expires_at = renewed.expires_at.unwrap();
+        assert!((unix_now() + 599..=unix_now() + 600).contains(&expires_at), "{}", expires_at);
+        assert_eq!(manager.get_reservation(tcp(port)).unwrap().expires_at, Some(expires_at));
+
+        let renewed = manager.renew_lease(tcp(port), Some(Duration::from_secs(30))).unwrap();
+        assert_eq!(renewed.ttl_secs, Some(30));
+    }
+
+    #[test]
+    fn renewing_a_permanent_reservation_needs_a_ttl() {
+        let manager = PortReservationManager::new();
+        let port = free_port();
+        manager.reserve(port, ReservationRequest::new("web")).unwrap();
+        let err = manager.renew_lease(tcp(port), None).unwrap_err();
+        assert!(matches!(err, ReservationError::Invalid(_)), "{:?}", err);
+        assert_eq!(manager.get_reservation(tcp(port)).unwrap().expires_at, None);
+
+        // A TTL turns it into a lease
+        let renewed = manager.renew_lease(tcp(port), Some(Duration::from_secs(60))).unwrap();
+        assert_eq!(renewed.ttl_secs, Some(60));
+        assert!(manager.get_reservation(tcp(port)).unwrap().expires_at.is_some());
+    }
+
+    #[test]
+    fn reaps_only_expired_leases() {
+        let manager = PortReservationManager::new();
+        let range = free_range(3);
+        let (expired, live, permanent) = (range.start, range.start + 1, range.start + 2);
+        manager.reserve(expired, lease(60)).unwrap();
+        manager.reserve(live, lease(60)).unwrap();
+        manager.reserve(permanent, ReservationRequest::new("web")).unwrap();
+        set_expiry(&manager, expired, unix_now() - 1);
+        assert!(manager.get_reservation(tcp(expired)).is_none());
+
+        let reaped = manager.reap_expired().unwrap();
+        assert_eq!(reaped.iter().map(|r| r.port).collect::<Vec<_>>(), [expired]);
+        let mut left: Vec<_> = manager.get_all_reservations().iter().map(|r| r.port).collect();
+        left.sort();
+        assert_eq!(left, [live, permanent]);
+        assert!(manager.reap_expired().unwrap().is_empty());
+    }
+
+    #[test]
+    fn an_expired_lease_doesnt_block_a_new_reservation() {
+        let manager = PortReservationManager::new();
+        let port = free_port();
+        manager.reserve(port, ReservationRequest { owner: Some("alice".to_string()), ..lease(60) }).unwrap();
+        set_expiry(&manager, port, unix_now() - 1);
+        let taken = manager.reserve(port, ReservationRequest { owner: Some("bob".to_string()), ..lease(60) }).unwrap();
+        assert_eq!(taken.owner.as_deref(), Some("bob"));
+        assert_eq!(manager.get_all_reservations().len(), 1);
+        // The new holder's lease isn't what gets reaped
+        assert!(manager.reap_expired().unwrap().is_empty());
+        assert_eq!(manager.get_reservation(tcp(port)).unwrap().owner.as_deref(), Some("bob"));
+    }
+
+    fn pool(range: PortRange, max_per_owner: Option<u32>, default_ttl_secs: Option<u64>) -> Pool {
+        Pool { name: "web".to_string(), range, max_per_owner, default_ttl_secs }
+    }
+
+    fn owned_by(owner: Option<&str>) -> ReservationRequest {
+        ReservationRequest { owner: owner.map(str::to_string), ..ReservationRequest::new("web") }
+    }
+
+    #[test]
+    fn holds_each_owner_to_the_pool_limit() {
+        let range = free_range(4);
+        let manager = PortReservationManager::new().with_pools(vec![pool(range, Some(2), None)]);
+        manager.reserve(range.start, owned_by(Some("alice"))).unwrap();
+        manager.reserve(range.start + 1, owned_by(Some("alice"))).unwrap();
+        let err = manager.reserve(range.start + 2, owned_by(Some("alice"))).unwrap_err();
+        assert_eq!(err.code(), "LIMIT_REACHED", "{:?}", err);
+        let err = manager.allocate(None, ReservationRequest { pool: Some("web".to_string()), ..owned_by(Some("alice")) });
+        assert_eq!(err.unwrap_err().code(), "LIMIT_REACHED");
+        manager.reserve(range.start + 2, owned_by(Some("bob"))).unwrap();
+
+        // Releasing one makes room again
+        manager.release(ReservationKey { protocol: Protocol::Tcp, port: range.start, bind_addr: None }).unwrap();
+        manager.reserve(range.start + 3, owned_by(Some("alice"))).unwrap();
+    }
+
+    #[test]
+    fn counts_anonymous_reservations_as_one_owner() {
+        let range = free_range(3);
+        let manager = PortReservationManager::new().with_pools(vec![pool(range, Some(1), None)]);
+        manager.reserve(range.start, owned_by(None)).unwrap();
+        let err = manager.reserve(range.start + 1, owned_by(None)).unwrap_err();
+        assert_eq!(err.code(), "LIMIT_REACHED", "{:?}", err);
+        assert!(err.message().starts_with("Anonymous owner"), "{}", err.message());
+        manager.reserve(range.start + 1, owned_by(Some("alice"))).unwrap();
+    }
+
+    #[test]
+    fn applies_the_pool_default_ttl() {
+        let range = free_range(3);
+        let manager = PortReservationManager::new().with_pools(vec![pool(range, None, Some(3600))]);
+        let defaulted = manager.reserve(range.start, ReservationRequest::new("web")).unwrap();
+        assert_eq!((defaulted.ttl_secs, defaulted.pool.as_deref()), (Some(3600), Some("web")));
+        assert!(defaulted.expires_at.is_some_and(|at| at >= unix_now() + 3599));
+        let asked = manager.reserve(range.start + 1, lease(60)).unwrap();
+        assert_eq!(asked.ttl_secs, Some(60));
+
+        let outside = manager.reserve(free_port(), ReservationRequest::new("web")).unwrap();
+        assert_eq!((outside.ttl_secs, outside.expires_at, outside.pool), (None, None, None));
+    }
+
+    #[test]
+    fn opens_an_empty_database_at_user_version_0() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("reservations.db");
+        Connection::open(&path).unwrap();
+        assert_eq!(user_version(&path), 0);
+        let manager = PortReservationManager::open(&path).unwrap();
+        assert_eq!(user_version(&path), MIGRATIONS.len());
+        let port = free_port();
+        manager.reserve(port, ReservationRequest::new("web")).unwrap();
+        assert_eq!(manager.get_service(port).as_deref(), Some("web"));
+    }
+
+    #[test]
+    fn upgrades_rows_from_the_first_schema() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("reservations.db");
+        {
+            let mut conn = Connection::open(&path).unwrap();
+            migrate(&mut conn, &MIGRATIONS[..1]).unwrap();
+            conn.execute(
+                "INSERT INTO reservations (port, protocol, service, owner, created_at) VALUES (8080, 'TCP', 'web', 'alice', 100)",
+                [],
+            )
+            .unwrap();
+        }
+        assert_eq!(user_version(&path), 1);
+        let manager = PortReservationManager::open(&path).unwrap();
+        assert_eq!(user_version(&path), MIGRATIONS.len());
+        let r = manager.get_reservation(ReservationKey::tcp(8080)).unwrap();
+        assert_eq!((r.service.as_str(), r.owner.as_deref(), r.created_at), ("web", Some("alice"), 100));
+        assert_eq!((r.bind_addr, r.ttl_secs, r.pool, r.notes), (None, None, None, None));
+        // Running the migrations again is a no-op
+        drop(manager);
+        PortReservationManager::open(&path).unwrap();
+        assert_eq!(user_version(&path), MIGRATIONS.len());
+    }
+
+    #[test]
+    fn imports_legacy_json_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("reservations.db");
+        let legacy = dir.path().join("reservations.json");
+        fs::write(&legacy, r#"{"8080": "web", "5432": "db"}"#).unwrap();
+        let manager = PortReservationManager::open(&path).unwrap();
+        assert_eq!(manager.get_service(8080).as_deref(), Some("web"));
+        assert_eq!(manager.get_service(5432).as_deref(), Some("db"));
+        assert!(!legacy.exists());
+        assert!(dir.path().join("reservations.json.imported").exists());
+
+        // Gone once imported, and ports already in the database are left alone
+        assert_eq!(manager.import_legacy_json(&legacy), Ok(0));
+        fs::write(&legacy, r#"{"8080": "other", "9090": "metrics"}"#).unwrap();
+        assert_eq!(manager.import_legacy_json(&legacy), Ok(1));
+        assert_eq!(manager.get_service(8080).as_deref(), Some("web"));
+        assert_eq!(manager.get_service(9090).as_deref(), Some("metrics"));
+        drop(manager);
+        let manager = PortReservationManager::open(&path).unwrap();
+        assert_eq!(manager.get_all_reservations().len(), 3);
+    }
+
+    #[test]
+    fn concurrent_allocations_get_distinct_ports() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("reservations.db");
+        let managers = [
+            Arc::new(PortReservationManager::open(&path).unwrap()),
+            Arc::new(PortReservationManager::open(&path).unwrap()),
+        ];
+        let range = free_range(3);
+        let barrier = Arc::new(Barrier::new(6));
+        let handles: Vec<_> = (0..6)
+            .map(|i| {
+                let manager = managers[i % 2].clone();
+                let barrier = barrier.clone();
+                thread::spawn(move || {
+                    barrier.wait();
+                    manager.allocate(Some(range), ReservationRequest::new(format!("service-{}", i)))
+                })
+            })
+            .collect();
+        let results: Vec<_> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+        let mut ports: Vec<u16> = results.iter().flatten().map(|r| r.port).collect();
+        ports.sort();
+        assert_eq!(ports, (range.start..=range.end).collect::<Vec<_>>(), "{:?}", results);
+        for result in &results {
+            if let Err(e) = result {
+                assert!(matches!(e, ReservationError::NoFreePort(_)), "{:?}", e);
+            }
+        }
+        let next = managers[0].allocate(Some(range), ReservationRequest::new("late"));
+        assert_eq!(next.unwrap_err().code(), "NO_FREE_PORT");
+    }
+
+    #[test]
+    fn allocate_needs_a_range_or_a_known_pool() {
+        let manager = PortReservationManager::new();
+        let err = manager.allocate(None, ReservationRequest::new("web")).unwrap_err();
+        assert_eq!(err.code(), "INVALID_REQUEST");
+        let mut req = ReservationRequest::new("web");
+        req.pool = Some("missing".to_string());
+        assert_eq!(manager.allocate(None, req).unwrap_err().code(), "INVALID_REQUEST");
+    }
+
+    #[test]
+    fn validates_port_ranges() {
+        assert_eq!("3000-3999".parse(), Ok(PortRange { start: 3000, end: 3999 }));
+        assert_eq!(" 8080 ".parse(), Ok(PortRange { start: 8080, end: 8080 }));
+        assert!(PortRange::new(4000, 3000).is_err());
+        assert!("4000-3000".parse::<PortRange>().is_err());
+        assert!(PortRange::new(0, 10).is_err());
+        assert!("0".parse::<PortRange>().is_err());
+        assert!("0-100".parse::<PortRange>().is_err());
+        assert!("3000-70000".parse::<PortRange>().is_err());
+        assert!("web".parse::<PortRange>().is_err());
+    }
+}
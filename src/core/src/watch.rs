@@ -0,0 +1,194 @@
+use crate::net::{PortInfo, Protocol};
+use crate::reservation::{Reservation, ReservationKey};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// A change between two snapshots of sockets and reservations
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum PortEvent {
+    SocketOpened { socket: PortInfo },
+    SocketClosed { socket: PortInfo },
+    StateChanged { socket: PortInfo, previous_state: String },
+    Reserved { reservation: Reservation },
+    Released { reservation: Reservation },
+}
+
+impl PortEvent {
+    /// The `type` this event serializes with
+    pub fn kind(&self) -> &'static str {
+        match self {
+            PortEvent::SocketOpened { .. } => "socket_opened",
+            PortEvent::SocketClosed { .. } => "socket_closed",
+            PortEvent::StateChanged { .. } => "state_changed",
+            PortEvent::Reserved { .. } => "reserved",
+            PortEvent::Released { .. } => "released",
+        }
+    }
+
+    /// Protocol and port the event is about
+    pub fn port(&self) -> (Protocol, u16) {
+        match self {
+            PortEvent::SocketOpened { socket }
+            | PortEvent::SocketClosed { socket }
+            | PortEvent::StateChanged { socket, .. } => (socket.protocol, socket.port),
+            PortEvent::Reserved { reservation } | PortEvent::Released { reservation } => {
+                (reservation.protocol, reservation.port)
+            }
+        }
+    }
+}
+
+/// A socket is the same socket across snapshots while its addresses and owner stay put
+type SocketId = (Protocol, String, String, Option<i32>);
+
+fn socket_id(p: &PortInfo) -> SocketId {
+    (p.protocol, p.local_addr.clone(), p.remote_addr.clone(), p.pid)
+}
+
+/// Turns successive `list_ports`/`get_all_reservations` snapshots into events.
+/// The first `update` reports everything as new.
+#[derive(Debug, Default)]
+pub struct PortWatcher {
+    sockets: HashMap<SocketId, PortInfo>,
+    reservations: HashMap<ReservationKey, Reservation>,
+}
+
+impl PortWatcher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replace the last snapshot with this one and return what changed
+    pub fn update(&mut self, ports: Vec<PortInfo>, reservations: Vec<Reservation>) -> Vec<PortEvent> {
+        let mut events = Vec::new();
+
+        let sockets: HashMap<SocketId, PortInfo> = ports.into_iter().map(|p| (socket_id(&p), p)).collect();
+        for (id, new) in &sockets {
+            match self.sockets.get(id) {
+                None => events.push(PortEvent::SocketOpened { socket: new.clone() }),
+                Some(old) if old.state != new.state => events.push(PortEvent::StateChanged {
+                    socket: new.clone(),
+                    previous_state: old.state.clone(),
+                }),
+                Some(_) => {}
+            }
+        }
+        for (id, old) in self.sockets.drain() {
+            if !sockets.contains_key(&id) {
+                events.push(PortEvent::SocketClosed { socket: old });
+            }
+        }
+        self.sockets = sockets;
+
+        let reservations: HashMap<ReservationKey, Reservation> =
+            reservations.into_iter().map(|r| (r.key(), r)).collect();
+        for (key, new) in &reservations {
+            if !self.reservations.contains_key(key) {
+                events.push(PortEvent::Reserved { reservation: new.clone() });
+            }
+        }
+        for (key, old) in self.reservations.drain() {
+            if !reservations.contains_key(&key) {
+                events.push(PortEvent::Released { reservation: old });
+            }
+        }
+        self.reservations = reservations;
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn socket(protocol: Protocol, local_addr: &str, remote_addr: &str, state: &str, pid: i32) -> PortInfo {
+        PortInfo {
+            local_addr: local_addr.to_string(),
+            remote_addr: remote_addr.to_string(),
+            state: state.to_string(),
+            pid: Some(pid),
+            process: Some("test".to_string()),
+            protocol,
+            port: local_addr.rsplit_once(':').unwrap().1.parse().unwrap(),
+            tags: Vec::new(),
+            user: None,
+        }
+    }
+
+    fn reservation(port: u16) -> Reservation {
+        Reservation {
+            port,
+            protocol: Protocol::Tcp,
+            bind_addr: None,
+            service: "web".to_string(),
+            owner: None,
+            created_at: 0,
+            expires_at: None,
+            ttl_secs: None,
+            pool: None,
+            notes: None,
+        }
+    }
+
+    /// `(type, port, previous state)` of each event, in a stable order
+    fn summary(events: &[PortEvent]) -> Vec<(&'static str, u16, Option<&str>)> {
+        let mut summary: Vec<_> = events
+            .iter()
+            .map(|e| {
+                let previous = match e {
+                    PortEvent::StateChanged { previous_state, .. } => Some(previous_state.as_str()),
+                    _ => None,
+                };
+                (e.kind(), e.port().1, previous)
+            })
+            .collect();
+        summary.sort();
+        summary
+    }
+
+    #[test]
+    fn reports_what_changed_between_snapshots() {
+        let listener = socket(Protocol::Tcp, "0.0.0.0:8080", "0.0.0.0:0", "Listen", 10);
+        let client = socket(Protocol::Tcp, "127.0.0.1:5432", "127.0.0.1:40000", "Established", 20);
+        let mut watcher = PortWatcher::new();
+
+        let first = watcher.update(vec![listener.clone(), client.clone()], vec![reservation(8080)]);
+        assert_eq!(
+            summary(&first),
+            [("reserved", 8080, None), ("socket_opened", 5432, None), ("socket_opened", 8080, None)]
+        );
+
+        let closing = PortInfo { state: "CloseWait".to_string(), ..client };
+        let dns = socket(Protocol::Udp, "127.0.0.53:53", "0.0.0.0:0", "", 30);
+        let second = watcher.update(vec![listener.clone(), closing, dns], vec![reservation(9090)]);
+        assert_eq!(
+            summary(&second),
+            [
+                ("released", 8080, None),
+                ("reserved", 9090, None),
+                ("socket_opened", 53, None),
+                ("state_changed", 5432, Some("Established")),
+            ]
+        );
+        match second.iter().find(|e| e.kind() == "state_changed") {
+            Some(PortEvent::StateChanged { socket, .. }) => assert_eq!(socket.state, "CloseWait"),
+            other => panic!("{:?}", other),
+        }
+
+        let ports = vec![listener.clone()];
+        let third = watcher.update(ports.clone(), vec![reservation(9090)]);
+        assert_eq!(summary(&third), [("socket_closed", 53, None), ("socket_closed", 5432, None)]);
+        // An unchanged snapshot produces nothing
+        assert!(watcher.update(ports, vec![reservation(9090)]).is_empty());
+
+        // The same address under another process is a different socket
+        let restarted = PortInfo { pid: Some(11), ..listener };
+        let fourth = watcher.update(vec![restarted], vec![]);
+        assert_eq!(
+            summary(&fourth),
+            [("released", 9090, None), ("socket_closed", 8080, None), ("socket_opened", 8080, None)]
+        );
+    }
+}
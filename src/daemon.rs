@@ -0,0 +1,525 @@
+//! Client for a running API server, reached over a Unix socket or plain HTTP.
+
+use port_manager_core::{
+    default_socket_paths, AuditEntry, PortEvent, PortRange, Reservation, ReservationKey, ReservationRequest,
+};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver};
+use std::time::Duration;
+use ureq::unversioned::resolver::DefaultResolver;
+use ureq::unversioned::transport::{Buffers, ConnectionDetails, Connector, LazyBuffers, NextTimeout, Transport};
+use ureq::{http, Agent};
+
+/// How long a request may take before the daemon counts as unreachable
+const TIMEOUT: Duration = Duration::from_secs(3);
+/// The server pings event streams every 15 seconds, so this much silence means it's gone
+const EVENTS_TIMEOUT: Duration = Duration::from_secs(45);
+
+/// Where the daemon listens
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Endpoint {
+    Unix(PathBuf),
+    /// `host:port` of a plain HTTP listener
+    Http(String),
+}
+
+impl Endpoint {
+    /// `unix:/path` or an absolute path for a Unix socket, `http://host:port` or
+    /// `host:port` for HTTP
+    pub fn parse(s: &str) -> Result<Self, String> {
+        if let Some(path) = s.strip_prefix("unix:") {
+            return Ok(Endpoint::Unix(PathBuf::from(path)));
+        }
+        if s.starts_with('/') {
+            return Ok(Endpoint::Unix(PathBuf::from(s)));
+        }
+        if s.starts_with("https://") {
+            return Err(format!("{}: only plain HTTP daemons are supported", s));
+        }
+        let host = s.strip_prefix("http://").unwrap_or(s).trim_end_matches('/');
+        if host.is_empty() || !host.contains(':') {
+            return Err(format!("{}: expected host:port or unix:/path", s));
+        }
+        Ok(Endpoint::Http(host.to_string()))
+    }
+}
+
+impl fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Endpoint::Unix(path) => write!(f, "unix:{}", path.display()),
+            Endpoint::Http(host) => write!(f, "http://{}", host),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum DaemonError {
+    /// No connection, a broken one, or a reply that isn't from the API
+    Unreachable(String),
+    /// The server refused the request with its usual error envelope
+    Api { code: String, message: String },
+}
+
+impl fmt::Display for DaemonError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DaemonError::Unreachable(e) => write!(f, "Daemon unreachable: {}", e),
+            DaemonError::Api { message, .. } => write!(f, "{}", message),
+        }
+    }
+}
+
+impl From<io::Error> for DaemonError {
+    fn from(e: io::Error) -> Self {
+        DaemonError::Unreachable(e.to_string())
+    }
+}
+
+#[derive(Deserialize)]
+struct ErrorBody {
+    code: String,
+    message: String,
+}
+
+enum Stream {
+    Tcp(TcpStream),
+    Unix(UnixStream),
+}
+
+impl Stream {
+    fn set_timeouts(&self, read: Option<Duration>, write: Option<Duration>) -> io::Result<()> {
+        match self {
+            Stream::Tcp(s) => s.set_read_timeout(read).and_then(|()| s.set_write_timeout(write)),
+            Stream::Unix(s) => s.set_read_timeout(read).and_then(|()| s.set_write_timeout(write)),
+        }
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(s) => s.read(buf),
+            Stream::Unix(s) => s.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(s) => s.write(buf),
+            Stream::Unix(s) => s.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stream::Tcp(s) => s.flush(),
+            Stream::Unix(s) => s.flush(),
+        }
+    }
+}
+
+impl Endpoint {
+    fn connect(&self) -> io::Result<Stream> {
+        Ok(match self {
+            Endpoint::Unix(path) => Stream::Unix(UnixStream::connect(path)?),
+            Endpoint::Http(host) => Stream::Tcp(TcpStream::connect(host)?),
+        })
+    }
+}
+
+/// Hands ureq a connection to the daemon whatever the URL's host, which is how requests
+/// reach a Unix socket. A read gives up after `idle` without data, or sooner if ureq's
+/// own deadline comes first.
+#[derive(Debug)]
+struct EndpointConnector {
+    endpoint: Endpoint,
+    idle: Duration,
+}
+
+impl Connector for EndpointConnector {
+    type Out = EndpointTransport;
+
+    fn connect(&self, details: &ConnectionDetails, _: Option<()>) -> Result<Option<Self::Out>, ureq::Error> {
+        let config = details.config;
+        let buffers = LazyBuffers::new(config.input_buffer_size(), config.output_buffer_size());
+        Ok(Some(EndpointTransport { stream: self.endpoint.connect()?, buffers, idle: self.idle }))
+    }
+}
+
+struct EndpointTransport {
+    stream: Stream,
+    buffers: LazyBuffers,
+    idle: Duration,
+}
+
+impl EndpointTransport {
+    /// Run `io` with the socket timing out at whichever of `timeout` and `idle` is sooner
+    fn timed<T>(&mut self, timeout: NextTimeout, io: impl FnOnce(&mut Self) -> io::Result<T>) -> Result<T, ureq::Error> {
+        let limit = timeout.not_zero().map_or(self.idle, |t| (*t).min(self.idle));
+        self.stream.set_timeouts(Some(limit), Some(limit))?;
+        io(self).map_err(|e| match e.kind() {
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => ureq::Error::Timeout(timeout.reason),
+            _ => e.into(),
+        })
+    }
+}
+
+impl Transport for EndpointTransport {
+    fn buffers(&mut self) -> &mut dyn Buffers {
+        &mut self.buffers
+    }
+
+    fn transmit_output(&mut self, amount: usize, timeout: NextTimeout) -> Result<(), ureq::Error> {
+        self.timed(timeout, |t| t.stream.write_all(&t.buffers.output()[..amount]))
+    }
+
+    fn await_input(&mut self, timeout: NextTimeout) -> Result<bool, ureq::Error> {
+        let read = self.timed(timeout, |t| t.stream.read(t.buffers.input_append_buf()))?;
+        self.buffers.input_appended(read);
+        Ok(read > 0)
+    }
+
+    /// Connections aren't reused; replies are small and the next request may be a while
+    fn is_open(&mut self) -> bool {
+        false
+    }
+}
+
+impl fmt::Debug for EndpointTransport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EndpointTransport").finish_non_exhaustive()
+    }
+}
+
+struct Response {
+    status: u16,
+    body: Box<dyn Read + Send>,
+}
+
+impl Response {
+    fn json<T: DeserializeOwned>(mut self) -> Result<T, DaemonError> {
+        let mut body = Vec::new();
+        self.body.read_to_end(&mut body)?;
+        if (200..300).contains(&self.status) {
+            return serde_json::from_slice(&body)
+                .map_err(|e| DaemonError::Unreachable(format!("unexpected reply: {}", e)));
+        }
+        let error = serde_json::from_slice(&body).unwrap_or_else(|_| ErrorBody {
+            code: format!("HTTP_{}", self.status),
+            message: String::from_utf8_lossy(&body).into_owned(),
+        });
+        Err(DaemonError::Api { code: error.code, message: error.message })
+    }
+}
+
+/// The fields `/reserve` and `/allocate` share. The owner is whoever the daemon says
+/// the caller is.
+fn request_body(req: &ReservationRequest) -> Value {
+    json!({
+        "service": req.service,
+        "protocol": req.protocol,
+        "bind_addr": req.bind_addr,
+        "ttl_secs": req.ttl.map(|ttl| ttl.as_secs()),
+        "pool": req.pool,
+        "notes": req.notes,
+    })
+}
+
+/// A daemon to send reservations to. Unix socket callers are known by their UID;
+/// over HTTP a token from `/login` is needed.
+pub struct Daemon {
+    pub endpoint: Endpoint,
+    token: Option<String>,
+}
+
+impl Daemon {
+    pub fn new(endpoint: Endpoint, token: Option<String>) -> Self {
+        Daemon { endpoint, token }
+    }
+
+    /// The daemon named by `PORTMGR_DAEMON`, with `PORTMGR_TOKEN` for HTTP, or else the
+    /// first of the default sockets that exists. `PORTMGR_DAEMON=local` opts out.
+    pub fn from_env() -> Result<Option<Self>, String> {
+        let token = std::env::var("PORTMGR_TOKEN").ok().filter(|t| !t.is_empty());
+        match std::env::var("PORTMGR_DAEMON").ok().filter(|d| !d.is_empty()) {
+            Some(d) if d == "local" => Ok(None),
+            Some(d) => Endpoint::parse(&d).map(|endpoint| Some(Daemon::new(endpoint, token))),
+            None => Ok(default_socket_paths()
+                .into_iter()
+                .find(|path| path.exists())
+                .map(|path| Daemon::new(Endpoint::Unix(path), token))),
+        }
+    }
+
+    /// Whether anything accepts connections at the endpoint
+    pub fn reachable(&self) -> bool {
+        self.endpoint.connect().is_ok()
+    }
+
+    /// Send a request, giving up after `timeout` (never, for `None`) or `EVENTS_TIMEOUT`
+    /// of silence
+    fn send(&self, method: &str, path: &str, body: Option<Value>, timeout: Option<Duration>) -> Result<Response, DaemonError> {
+        let config = Agent::config_builder()
+            .http_status_as_error(false)
+            .proxy(None)
+            .timeout_global(timeout)
+            .build();
+        let connector = EndpointConnector { endpoint: self.endpoint.clone(), idle: EVENTS_TIMEOUT };
+        let agent = Agent::with_parts(config, connector, DefaultResolver::default());
+        let host = match &self.endpoint {
+            Endpoint::Unix(_) => "localhost",
+            Endpoint::Http(host) => host,
+        };
+        let mut request = http::Request::builder().method(method).uri(format!("http://{}/api/v1{}", host, path));
+        if let Some(token) = &self.token {
+            request = request.header("Authorization", format!("Bearer {}", token));
+        }
+        if body.is_some() {
+            request = request.header("Content-Type", "application/json");
+        }
+        let request = request
+            .body(body.map(|b| b.to_string()).unwrap_or_default())
+            .map_err(|e| DaemonError::Unreachable(e.to_string()))?;
+        let response = agent.run(request).map_err(|e| DaemonError::Unreachable(e.to_string()))?;
+        Ok(Response { status: response.status().as_u16(), body: Box::new(response.into_body().into_reader()) })
+    }
+
+    fn request<T: DeserializeOwned>(&self, method: &str, path: &str, body: Option<Value>) -> Result<T, DaemonError> {
+        self.send(method, path, body, Some(TIMEOUT))?.json()
+    }
+
+    pub fn reservations(&self) -> Result<Vec<Reservation>, DaemonError> {
+        self.request("GET", "/reservations", None)
+    }
+
+    pub fn reserve(&self, port: u16, req: &ReservationRequest) -> Result<Reservation, DaemonError> {
+        let mut body = request_body(req);
+        body["port"] = json!(port);
+        self.request("POST", "/reserve", Some(body))
+    }
+
+    /// Reserve whichever port in `range`, or else in the request's pool, is free
+    pub fn allocate(&self, range: Option<PortRange>, req: &ReservationRequest) -> Result<Reservation, DaemonError> {
+        let mut body = request_body(req);
+        if let Some(range) = range {
+            body["port_start"] = json!(range.start);
+            body["port_end"] = json!(range.end);
+        }
+        self.request("POST", "/allocate", Some(body))
+    }
+
+    pub fn release(&self, key: ReservationKey) -> Result<Reservation, DaemonError> {
+        let body = json!({ "port": key.port, "protocol": key.protocol, "bind_addr": key.bind_addr });
+        self.request("POST", "/release", Some(body))
+    }
+
+    /// Release `held`, but only while it is still the reservation on its key: same owner
+    /// and creation time
+    pub fn release_exact(&self, held: &Reservation) -> Result<Reservation, DaemonError> {
+        let key = held.key();
+        let body = json!({
+            "port": key.port,
+            "protocol": key.protocol,
+            "bind_addr": key.bind_addr,
+            "owner": held.owner,
+            "created_at": held.created_at,
+        });
+        self.request("POST", "/release", Some(body))
+    }
+
+    /// Extend a lease by its own TTL
+    pub fn renew(&self, key: ReservationKey) -> Result<Reservation, DaemonError> {
+        let body = json!({ "port": key.port, "protocol": key.protocol, "bind_addr": key.bind_addr });
+        self.request("POST", "/renew", Some(body))
+    }
+
+    /// Newest audit entries first; needs the `view_audit` permission
+    pub fn audit(&self, limit: u32) -> Result<Vec<AuditEntry>, DaemonError> {
+        self.request("GET", &format!("/audit?limit={}", limit), None)
+    }
+
+    /// Follow `/events` on a background thread. The channel disconnects when the
+    /// stream ends or goes quiet.
+    pub fn subscribe(&self) -> Result<Receiver<PortEvent>, DaemonError> {
+        let response = self.send("GET", "/events", None, None)?;
+        if response.status != 200 {
+            return Err(match response.json::<Value>() {
+                Err(e) => e,
+                Ok(_) => DaemonError::Unreachable("no event stream".to_string()),
+            });
+        }
+        let (tx, rx) = mpsc::channel();
+        std::thread::spawn(move || {
+            for line in BufReader::new(response.body).lines() {
+                let Ok(line) = line else { break };
+                let event = match line.strip_prefix("data:") {
+                    Some(data) => serde_json::from_str::<PortEvent>(data.trim()),
+                    None => continue,
+                };
+                if let Ok(event) = event {
+                    if tx.send(event).is_err() {
+                        break;
+                    }
+                }
+            }
+        });
+        Ok(rx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::net::UnixListener;
+    use std::thread::{self, JoinHandle};
+
+    fn response(status: u16, body: &str) -> Response {
+        Response { status, body: Box::new(io::Cursor::new(body.as_bytes().to_vec())) }
+    }
+
+    /// Answer one request on `stream` with `reply`, returning the request's head and body
+    fn serve_one(stream: impl Read + Write, reply: String) -> String {
+        let mut reader = BufReader::new(stream);
+        let (mut request, mut length) = (String::new(), 0);
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            if let Some((name, value)) = line.split_once(':') {
+                if name.eq_ignore_ascii_case("content-length") {
+                    length = value.trim().parse().unwrap();
+                }
+            }
+            request.push_str(&line);
+            if line == "\r\n" {
+                break;
+            }
+        }
+        let mut body = vec![0; length];
+        reader.read_exact(&mut body).unwrap();
+        request.push_str(&String::from_utf8(body).unwrap());
+        reader.get_mut().write_all(reply.as_bytes()).unwrap();
+        request
+    }
+
+    fn unix_daemon(reply: String) -> (tempfile::TempDir, Daemon, JoinHandle<String>) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("api.sock");
+        let listener = UnixListener::bind(&path).unwrap();
+        let server = thread::spawn(move || serve_one(listener.accept().unwrap().0, reply));
+        (dir, Daemon::new(Endpoint::Unix(path), None), server)
+    }
+
+    #[test]
+    fn talks_http_over_a_unix_socket() {
+        let reply = "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nTransfer-Encoding: chunked\r\n\r\n\
+                     1\r\n[\r\n1\r\n]\r\n0\r\n\r\n";
+        let (_dir, daemon, server) = unix_daemon(reply.to_string());
+        assert!(daemon.reservations().unwrap().is_empty());
+        let request = server.join().unwrap();
+        assert!(request.starts_with("GET /api/v1/reservations HTTP/1.1\r\n"), "{}", request);
+        assert!(!request.to_ascii_lowercase().contains("authorization"), "{}", request);
+    }
+
+    #[test]
+    fn sends_json_and_the_token_over_tcp() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let host = listener.local_addr().unwrap().to_string();
+        let error = r#"{"code":"ALREADY_RESERVED","message":"Port 1 taken"}"#;
+        let reply = format!(
+            "HTTP/1.1 409 Conflict\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+            error.len(),
+            error
+        );
+        let server = thread::spawn(move || serve_one(listener.accept().unwrap().0, reply));
+        let daemon = Daemon::new(Endpoint::Http(host), Some("secret".to_string()));
+        match daemon.reserve(1, &ReservationRequest::new("web")) {
+            Err(DaemonError::Api { code, message }) => assert_eq!((code.as_str(), message.as_str()), ("ALREADY_RESERVED", "Port 1 taken")),
+            other => panic!("{:?}", other),
+        }
+        let request = server.join().unwrap();
+        assert!(request.starts_with("POST /api/v1/reserve HTTP/1.1\r\n"), "{}", request);
+        let lower = request.to_ascii_lowercase();
+        assert!(lower.contains("authorization: bearer secret\r\n"), "{}", request);
+        assert!(lower.contains("content-type: application/json\r\n"), "{}", request);
+        let body: Value = serde_json::from_str(&request[request.find("\r\n\r\n").unwrap() + 4..]).unwrap();
+        assert_eq!((body["port"].as_u64(), body["service"].as_str()), (Some(1), Some("web")));
+    }
+
+    #[test]
+    fn unreachable_without_a_daemon_or_an_http_reply() {
+        let dir = tempfile::tempdir().unwrap();
+        let daemon = Daemon::new(Endpoint::Unix(dir.path().join("nothing.sock")), None);
+        assert!(!daemon.reachable());
+        assert!(matches!(daemon.reservations(), Err(DaemonError::Unreachable(_))));
+
+        let (_dir, daemon, server) = unix_daemon("SSH-2.0-OpenSSH_9.6\r\n".to_string());
+        assert!(matches!(daemon.reservations(), Err(DaemonError::Unreachable(_))));
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn streams_events_until_the_server_hangs_up() {
+        let reservation = Reservation {
+            port: 8080,
+            protocol: Default::default(),
+            bind_addr: None,
+            service: "web".to_string(),
+            owner: None,
+            created_at: 0,
+            expires_at: None,
+            ttl_secs: None,
+            pool: None,
+            notes: None,
+        };
+        let event = serde_json::to_string(&PortEvent::Released { reservation }).unwrap();
+        let reply = format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\n\r\n: ping\n\nevent: released\ndata: {}\n\n",
+            event
+        );
+        let (_dir, daemon, server) = unix_daemon(reply);
+        let events = daemon.subscribe().unwrap();
+        server.join().unwrap();
+        let received: Vec<PortEvent> = events.iter().collect();
+        assert_eq!(received.iter().map(|e| e.port().1).collect::<Vec<_>>(), [8080]);
+    }
+
+    #[test]
+    fn maps_error_replies() {
+        match response(409, "{\"code\":\"ALREADY_RESERVED\",\"message\":\"Port 1 taken\"}").json::<Value>() {
+            Err(DaemonError::Api { code, message }) => assert_eq!((code.as_str(), message.as_str()), ("ALREADY_RESERVED", "Port 1 taken")),
+            other => panic!("{:?}", other),
+        }
+        match response(502, "upstream down").json::<Value>() {
+            Err(DaemonError::Api { code, message }) => assert_eq!((code.as_str(), message.as_str()), ("HTTP_502", "upstream down")),
+            other => panic!("{:?}", other),
+        }
+        assert!(matches!(response(200, "<html>").json::<Value>(), Err(DaemonError::Unreachable(_))));
+        assert_eq!(response(200, "[1, 2]").json::<Value>().unwrap(), serde_json::json!([1, 2]));
+    }
+    #[test]
+    fn parses_endpoints() {
+        let unix = |p: &str| Ok(Endpoint::Unix(PathBuf::from(p)));
+        let http = |h: &str| Ok(Endpoint::Http(h.to_string()));
+        assert_eq!(Endpoint::parse("unix:/run/port-manager.sock"), unix("/run/port-manager.sock"));
+        assert_eq!(Endpoint::parse("/tmp/pm.sock"), unix("/tmp/pm.sock"));
+        assert_eq!(Endpoint::parse("http://127.0.0.1:8080"), http("127.0.0.1:8080"));
+        assert_eq!(Endpoint::parse("http://localhost:8080/"), http("localhost:8080"));
+        assert_eq!(Endpoint::parse("[::1]:8080"), http("[::1]:8080"));
+        for bad in ["https://localhost:8443", "http://", "localhost", ""] {
+            assert!(Endpoint::parse(bad).is_err(), "{:?}", bad);
+        }
+        let endpoint = Endpoint::parse("unix:/run/port-manager.sock").unwrap();
+        assert_eq!(Endpoint::parse(&endpoint.to_string()), Ok(endpoint));
+        assert_eq!(Endpoint::Http("localhost:8080".to_string()).to_string(), "http://localhost:8080");
+    }
+}
@@ -5,6 +5,7 @@ use ratatui::{
     Frame,
 };
 use crate::app::App;
+use std::time::Duration;
 
 pub fn ui(f: &mut Frame, app: &App) {
     let chunks = Layout::default()
@@ -14,7 +15,7 @@ pub fn ui(f: &mut Frame, app: &App) {
             Constraint::Min(1),
             Constraint::Length(1),
         ])
-        .split(f.size());
+        .split(f.area());
 
     // Search box only if search_mode is active
     if app.search_mode {
@@ -40,6 +41,12 @@ pub fn ui(f: &mut Frame, app: &App) {
         Cell::from("Process"),
     ]).style(Style::default().fg(app.theme.header_fg).add_modifier(Modifier::BOLD));
 
+    // Where reservations are read from and sent to
+    let backend = match app.remote() {
+        Some(daemon) => daemon.endpoint.to_string(),
+        None => "local".to_string(),
+    };
+
     // Table rows
     let selected_idx = app.selected_index();
     let rows: Vec<Row> = app.filtered_ports.iter().enumerate().map(|(i, p)| {
@@ -54,15 +61,13 @@ pub fn ui(f: &mut Frame, app: &App) {
             "TIME_WAIT" => Color::Yellow,
             _ => app.theme.foreground,
         };
-        let reserved = if let Some(port) = p.local_addr.split(':').last() {
-            if let Ok(port_num) = port.parse::<u16>() {
-                if app.reservation_manager.is_reserved(port_num) {
-                    "(reserved)"
-                } else {
-                    ""
-                }
-            } else { "" }
-        } else { "" };
+        let reserved = match app.find_reservation(p.protocol, p.port, p.local_ip()) {
+            Some(r) => match r.remaining() {
+                Some(left) => format!("(reserved, {} left)", format_remaining(left)),
+                None => "(reserved)".to_string(),
+            },
+            None => String::new(),
+        };
         Row::new(vec![
             Cell::from(format!("{} {}", p.local_addr.clone(), reserved)),
             Cell::from(p.remote_addr.clone()),
@@ -80,13 +85,22 @@ pub fn ui(f: &mut Frame, app: &App) {
         Constraint::Percentage(20),
     ])
     .header(header)
-    .block(Block::default().borders(Borders::ALL).title(" Open Ports "))
-    .highlight_style(Style::default().fg(Color::Yellow).bg(Color::Blue));
+    .block(Block::default().borders(Borders::ALL).title(format!(" Open Ports (reservations: {}) ", backend)))
+    .row_highlight_style(Style::default().fg(Color::Yellow).bg(Color::Blue));
 
-    f.render_widget(table, chunks[1]);
+    if app.show_audit {
+        let halves = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Percentage(60), Constraint::Percentage(40)])
+            .split(chunks[1]);
+        f.render_widget(table, halves[0]);
+        render_audit(f, app, halves[1]);
+    } else {
+        f.render_widget(table, chunks[1]);
+    }
 
     // Status bar
-    let status_text = format!("j/k: move  |  c: kill  |  r: reserve  |  u: unreserve  |  q: quit  |  Filter: {}", app.search);
+    let status_text = format!("j/k: move  |  c: kill  |  r: reserve  |  u: unreserve  |  a: audit  |  q: quit  |  Filter: {}", app.search);
     let status = Paragraph::new(status_text)
         .block(Block::default().borders(Borders::ALL))
         .style(Style::default().fg(Color::White).bg(Color::DarkGray));
@@ -94,7 +108,7 @@ pub fn ui(f: &mut Frame, app: &App) {
 
     // Reservation popup
     if let Some((port, service)) = &app.reservation_popup {
-        let area = centered_rect(50, 20, f.size());
+        let area = centered_rect(50, 20, f.area());
         let msg = format!("Port {} reserved for '{}'. Press any key to continue.", port, service);
         let popup = Paragraph::new(msg)
             .block(Block::default().borders(Borders::ALL).title(" Reservation "))
@@ -104,7 +118,7 @@ pub fn ui(f: &mut Frame, app: &App) {
         f.render_widget(popup, area);
     }
     if let Some(err) = &app.reservation_error {
-        let area = centered_rect(50, 20, f.size());
+        let area = centered_rect(50, 20, f.area());
         let popup = Paragraph::new(err.clone())
             .block(Block::default().borders(Borders::ALL).title(" Error "))
             .alignment(Alignment::Center)
@@ -114,6 +128,59 @@ pub fn ui(f: &mut Frame, app: &App) {
     }
 }
 
+/// Recent audit entries, newest first
+fn render_audit(f: &mut Frame, app: &App, area: Rect) {
+    let header = Row::new(vec![
+        Cell::from("When"),
+        Cell::from("Actor"),
+        Cell::from("Action"),
+        Cell::from("Target"),
+        Cell::from("Result"),
+        Cell::from("Detail"),
+    ]).style(Style::default().fg(app.theme.header_fg).add_modifier(Modifier::BOLD));
+    let now = port_manager_core::unix_now();
+    let rows: Vec<Row> = app.audit_entries.iter().map(|e| {
+        let target = match (e.protocol, e.port, e.pid) {
+            (Some(protocol), Some(port), Some(pid)) => format!("{} {} pid {}", protocol, port, pid),
+            (Some(protocol), Some(port), None) => format!("{} {}", protocol, port),
+            (_, _, Some(pid)) => format!("pid {}", pid),
+            _ => "-".to_string(),
+        };
+        let result_color = if e.result == port_manager_core::AUDIT_OK { Color::Green } else { Color::Red };
+        Row::new(vec![
+            Cell::from(format!("{} ago", format_remaining(Duration::from_secs(now.saturating_sub(e.timestamp))))),
+            Cell::from(e.actor.clone()),
+            Cell::from(e.action.clone()),
+            Cell::from(target),
+            Cell::from(e.result.clone()).style(Style::default().fg(result_color)),
+            Cell::from(e.detail.clone().unwrap_or_default()),
+        ]).style(Style::default().fg(app.theme.foreground).bg(app.theme.background))
+    }).collect();
+    let table = Table::new(rows, [
+        Constraint::Length(10),
+        Constraint::Length(12),
+        Constraint::Length(9),
+        Constraint::Length(22),
+        Constraint::Length(18),
+        Constraint::Min(10),
+    ])
+    .header(header)
+    .block(Block::default().borders(Borders::ALL).title(" Audit Log (a/Esc to close) "));
+    f.render_widget(table, area);
+}
+
+/// Compact duration for lease countdowns and ages, e.g. `1h05m`, `4m12s`, `9s`
+fn format_remaining(left: Duration) -> String {
+    let secs = left.as_secs();
+    if secs >= 3600 {
+        format!("{}h{:02}m", secs / 3600, secs % 3600 / 60)
+    } else if secs >= 60 {
+        format!("{}m{:02}s", secs / 60, secs % 60)
+    } else {
+        format!("{}s", secs)
+    }
+}
+
 fn centered_rect(percent_x: u16, percent_y: u16, r: Rect) -> Rect {
     let vertical_layout = Layout::default()
         .direction(Direction::Vertical)